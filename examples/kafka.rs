@@ -2,9 +2,8 @@ use std::collections::HashMap;
 use std::env;
 use std::thread;
 
-use eventio::fluentd::{Entry, ForwardMode};
+use eventio::fluentd::{Entry, EventTime, ForwardMode, Value};
 use eventio::{kafka, Input};
-use serde_bytes::ByteBuf;
 
 const TOPIC: &str = "eventio-examples";
 
@@ -28,10 +27,10 @@ fn produce(hosts: Vec<String>) {
         let out_thread = thread::spawn(move || output.run().unwrap());
 
         let mut record = HashMap::new();
-        record.insert("message".into(), ByteBuf::from(b"\x01\x02\x03".to_vec()));
+        record.insert("message".into(), Value::Binary(b"\x01\x02\x03".to_vec()));
         let entry = Entry {
-            time: 123,
-            record: record,
+            time: EventTime::new(123, 456),
+            record,
         };
         let msg = ForwardMode {
             tag: "tag".into(),
@@ -61,7 +60,7 @@ fn consume(hosts: Vec<String>) {
     let in_thread = thread::spawn(move || input.run().unwrap());
 
     let mut entry = Entry {
-        time: 0,
+        time: EventTime::default(),
         record: HashMap::new(),
     };
     {
@@ -73,6 +72,9 @@ fn consume(hosts: Vec<String>) {
     }
     in_thread.join().unwrap();
 
-    assert_eq!(entry.time, 123);
-    assert_eq!(entry.record["message"], b"\x01\x02\x03");
+    assert_eq!(entry.time, EventTime::new(123, 456));
+    assert_eq!(
+        entry.record["message"],
+        Value::Binary(b"\x01\x02\x03".to_vec())
+    );
 }
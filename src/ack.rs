@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Tracks outstanding events in multiple streams, such as Kafka partitions or
+/// files, to find the highest position in each stream up to which every event
+/// has been acknowledged.
+///
+/// Events can be acknowledged in any order. A position may hold more than one
+/// event, e.g., a Kafka message with multiple entries, and it is fully
+/// acknowledged when all of them are.
+#[derive(Debug)]
+pub struct AckTracker<S, P> {
+    streams: HashMap<S, Stream<P>>,
+}
+
+#[derive(Debug)]
+struct Stream<P> {
+    /// The number of unacknowledged events at each position.
+    pending: BTreeMap<P, usize>,
+    watermark: Option<P>,
+    advanced: bool,
+}
+
+impl<S, P> Default for AckTracker<S, P> {
+    fn default() -> Self {
+        Self {
+            streams: HashMap::new(),
+        }
+    }
+}
+
+impl<S, P> AckTracker<S, P>
+where
+    S: Clone + Eq + Hash,
+    P: Clone + Ord,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking `count` events at `pos` in `stream`. Positions in a
+    /// stream should be tracked in increasing order.
+    pub fn track(&mut self, stream: S, pos: P, count: usize) {
+        let stream = self.streams.entry(stream).or_insert_with(|| Stream {
+            pending: BTreeMap::new(),
+            watermark: None,
+            advanced: false,
+        });
+        *stream.pending.entry(pos).or_default() += count;
+        stream.advance();
+    }
+
+    /// Acknowledges an event at `pos` in `stream`. Returns `false` if there is
+    /// no outstanding event at `pos`.
+    pub fn ack(&mut self, stream: &S, pos: &P) -> bool {
+        let Some(stream) = self.streams.get_mut(stream) else {
+            return false;
+        };
+        let Some(remaining) = stream.pending.get_mut(pos) else {
+            return false;
+        };
+        if *remaining == 0 {
+            return false;
+        }
+        *remaining -= 1;
+        stream.advance();
+        true
+    }
+
+    /// Returns the highest position in `stream` such that all events tracked
+    /// at or before it have been acknowledged.
+    pub fn watermark(&self, stream: &S) -> Option<&P> {
+        self.streams.get(stream)?.watermark.as_ref()
+    }
+
+    /// Returns the number of events tracked in `stream` but not acknowledged
+    /// yet.
+    pub fn outstanding(&self, stream: &S) -> usize {
+        self.streams
+            .get(stream)
+            .map_or(0, |stream| stream.pending.values().sum())
+    }
+
+    /// Returns the watermarks of the streams whose watermark has advanced
+    /// since the last call.
+    pub fn advanced(&mut self) -> Vec<(S, P)> {
+        self.streams
+            .iter_mut()
+            .filter_map(|(id, stream)| {
+                if !stream.advanced {
+                    return None;
+                }
+                stream.advanced = false;
+                let watermark = stream.watermark.clone()?;
+                Some((id.clone(), watermark))
+            })
+            .collect()
+    }
+}
+
+impl<P: Ord> Stream<P> {
+    /// Moves the watermark past the fully acknowledged positions at the front.
+    fn advance(&mut self) {
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.get() > 0 {
+                break;
+            }
+            self.watermark = Some(entry.remove_entry().0);
+            self.advanced = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AckTracker;
+
+    #[test]
+    fn out_of_order() {
+        let mut tracker = AckTracker::new();
+        tracker.track(0, 10, 1);
+        tracker.track(0, 11, 2);
+        tracker.track(0, 12, 1);
+        assert_eq!(tracker.outstanding(&0), 4);
+
+        assert!(tracker.ack(&0, &12));
+        assert!(tracker.ack(&0, &11));
+        assert_eq!(tracker.watermark(&0), None);
+        assert!(tracker.advanced().is_empty());
+
+        assert!(tracker.ack(&0, &10));
+        assert_eq!(tracker.watermark(&0), Some(&10));
+        assert_eq!(tracker.advanced(), [(0, 10)]);
+        assert!(tracker.advanced().is_empty());
+
+        assert!(tracker.ack(&0, &11));
+        assert_eq!(tracker.watermark(&0), Some(&12));
+        assert_eq!(tracker.outstanding(&0), 0);
+    }
+
+    #[test]
+    fn streams() {
+        let mut tracker = AckTracker::new();
+        tracker.track("a", 1, 1);
+        tracker.track("b", 1, 1);
+        tracker.track("b", 2, 0);
+        assert!(tracker.ack(&"b", &1));
+        assert_eq!(tracker.watermark(&"a"), None);
+        assert_eq!(tracker.watermark(&"b"), Some(&2));
+        assert_eq!(tracker.advanced(), [("b", 2)]);
+    }
+
+    #[test]
+    fn unknown_ack() {
+        let mut tracker = AckTracker::new();
+        tracker.track(0, 1, 1);
+        assert!(!tracker.ack(&0, &2));
+        assert!(!tracker.ack(&1, &1));
+        assert!(tracker.ack(&0, &1));
+        assert!(!tracker.ack(&0, &1));
+    }
+}
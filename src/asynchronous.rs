@@ -0,0 +1,44 @@
+//! Channel handling shared by asynchronous inputs.
+
+use tokio::sync::mpsc::{Permit, Receiver, Sender};
+
+/// What happened first while waiting for room in a data channel.
+pub(crate) enum Ready<'a, D, A> {
+    /// The data channel has room for one event.
+    Send(Permit<'a, D>),
+    /// An ACK arrived.
+    Ack(A),
+    /// Either the data channel or the ACK channel was closed.
+    Closed,
+}
+
+/// Waits until `data_channel` has room for an event, or an ACK arrives through
+/// `ack_channel`.
+pub(crate) async fn ready<'a, D, A>(
+    data_channel: &'a Sender<D>,
+    ack_channel: &mut Receiver<A>,
+) -> Ready<'a, D, A> {
+    tokio::select! {
+        permit = data_channel.reserve() => permit.map_or(Ready::Closed, Ready::Send),
+        ack = ack_channel.recv() => ack.map_or(Ready::Closed, Ready::Ack),
+    }
+}
+
+/// Sends `event` through `data_channel`, discarding ACKs that arrive in the
+/// meantime. Returns `false` if either channel was closed.
+pub(crate) async fn send<D, A>(
+    data_channel: &Sender<D>,
+    ack_channel: &mut Receiver<A>,
+    event: D,
+) -> bool {
+    loop {
+        match ready(data_channel, ack_channel).await {
+            Ready::Send(permit) => {
+                permit.send(event);
+                return true;
+            }
+            Ready::Ack(_) => {}
+            Ready::Closed => return false,
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! Sending events in batches, each acknowledged by a single ACK.
+//!
+//! Sending an event and its ACK through channels costs about as much as
+//! processing a small event, such as a line or a packet. An input turned into
+//! a batch [`Input`] sends up to a given number of events at a time instead,
+//! and the receiver acknowledges all of them at once with [`Batch::ack`].
+
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::driver::{forward, wait_for_ack};
+use crate::{Error, Event, SeqNo, Shutdown, Source};
+
+/// An ACK for the events in a batch, which have consecutive sequence numbers.
+pub type Ack = RangeInclusive<SeqNo>;
+
+/// Events with consecutive sequence numbers, sent together.
+#[derive(Debug)]
+pub struct Batch<E> {
+    events: Vec<E>,
+    first: SeqNo,
+    last: SeqNo,
+}
+
+impl<E: Event<Ack = SeqNo>> Batch<E> {
+    /// Returns `None` if `events` is empty.
+    fn new(events: Vec<E>) -> Option<Self> {
+        let first = events.first()?.ack();
+        let last = events.last()?.ack();
+        Some(Self {
+            events,
+            first,
+            last,
+        })
+    }
+}
+
+impl<E> Batch<E> {
+    #[must_use]
+    pub fn events(&self) -> &[E] {
+        &self.events
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Always returns `false`, since an empty batch is never sent.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns the `Ack` that acknowledges every event in the batch.
+    #[must_use]
+    pub fn ack(&self) -> Ack {
+        self.first..=self.last
+    }
+}
+
+impl<E> IntoIterator for Batch<E> {
+    type Item = E;
+    type IntoIter = std::vec::IntoIter<E>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.events.into_iter()
+    }
+}
+
+/// How many events to put in a batch.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    size: usize,
+    window: Option<Duration>,
+}
+
+impl Config {
+    /// Creates `Config` for batches of up to `size` events.
+    ///
+    /// A batch smaller than `size` is sent as soon as the input has no more
+    /// events at the moment, unless a window is set by [`Config::with_window`].
+    #[must_use]
+    pub fn new(size: usize) -> Self {
+        Self {
+            size: size.max(1),
+            window: None,
+        }
+    }
+
+    /// Waits up to `window` after the first event of a batch for more events
+    /// to fill it, when the input has no more events at the moment. A batch
+    /// is sent once the window has passed, even if the input keeps producing
+    /// events.
+    ///
+    /// Only an input that polls for new events, such as a text file followed
+    /// by [`text::Input::follow`], waits within the window. Any other input
+    /// sends a smaller batch as soon as it has no more events at the moment,
+    /// as if no window were set.
+    ///
+    /// [`text::Input::follow`]: crate::text::Input::follow
+    #[must_use]
+    pub fn with_window(self, window: Duration) -> Self {
+        Self {
+            window: Some(window),
+            ..self
+        }
+    }
+}
+
+/// An input that sends events in batches.
+///
+/// It is created by a batched constructor of an input, such as
+/// [`text::Input::with_read_batched`], or by `batched` of an input, such as
+/// [`text::Input::batched`].
+///
+/// [`text::Input::with_read_batched`]: crate::text::Input::with_read_batched
+/// [`text::Input::batched`]: crate::text::Input::batched
+pub struct Input<E> {
+    source: Box<dyn Source<Data = E, Ack = SeqNo> + Send>,
+    data_channel: Sender<Batch<E>>,
+    ack_channel: Receiver<Ack>,
+    config: Config,
+    shutdown: Option<Shutdown>,
+}
+
+impl<E> Input<E> {
+    pub(crate) fn new(
+        source: Box<dyn Source<Data = E, Ack = SeqNo> + Send>,
+        data_channel: Sender<Batch<E>>,
+        ack_channel: Receiver<Ack>,
+        config: Config,
+        shutdown: Option<Shutdown>,
+    ) -> Self {
+        Self {
+            source,
+            data_channel,
+            ack_channel,
+            config,
+            shutdown,
+        }
+    }
+
+    /// Stops reading events once `shutdown` is triggered.
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+}
+
+impl<E: Event<Ack = SeqNo>> crate::Input for Input<E> {
+    type Data = Batch<E>;
+    type Ack = Ack;
+
+    /// Reads events and sends them in batches through `data_channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying input fails to fetch an event or to
+    /// process an ACK.
+    fn run(mut self) -> Result<(), Error> {
+        drive_until(
+            self.source.as_mut(),
+            self.data_channel,
+            &self.ack_channel,
+            self.config,
+            self.shutdown.as_ref(),
+        )
+    }
+}
+
+/// Sends events from `source` in batches through `data_channel` while passing
+/// the ACKs for each batch received from `ack_channel` to `source`, one
+/// sequence number at a time.
+///
+/// It works the same as [`drive`](crate::drive) otherwise. Events whose
+/// sequence numbers are not consecutive are put in separate batches.
+///
+/// # Errors
+///
+/// Returns an error if `source` fails to fetch an event or to process an ACK.
+pub fn drive<S>(
+    source: &mut S,
+    data_channel: Sender<Batch<S::Data>>,
+    ack_channel: &Receiver<Ack>,
+    config: Config,
+) -> Result<(), Error>
+where
+    S: Source<Ack = SeqNo> + ?Sized,
+    S::Data: Event<Ack = SeqNo>,
+{
+    drive_until(source, data_channel, ack_channel, config, None)
+}
+
+fn drive_until<S>(
+    source: &mut S,
+    data_channel: Sender<Batch<S::Data>>,
+    ack_channel: &Receiver<Ack>,
+    config: Config,
+    shutdown: Option<&Shutdown>,
+) -> Result<(), Error>
+where
+    S: Source<Ack = SeqNo> + ?Sized,
+    S::Data: Event<Ack = SeqNo>,
+{
+    let mut held = None;
+    forward(
+        source,
+        data_channel,
+        ack_channel,
+        shutdown,
+        |source, ack_channel| {
+            let mut events: Vec<S::Data> = Vec::with_capacity(config.size);
+            let mut deadline = None;
+            if let Some(event) = held.take() {
+                deadline = config.window.map(|window| Instant::now() + window);
+                events.push(event);
+            }
+            while events.len() < config.size {
+                if let Some(event) = source.next_event()? {
+                    if let Some(last) = events.last() {
+                        if last.ack().checked_add(1) != Some(event.ack()) {
+                            held = Some(event);
+                            break;
+                        }
+                    } else {
+                        deadline = config.window.map(|window| Instant::now() + window);
+                    }
+                    events.push(event);
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        break;
+                    }
+                    continue;
+                }
+                let Some(interval) = source.poll_interval() else {
+                    break;
+                };
+                let timeout = if events.is_empty() {
+                    interval
+                } else {
+                    let Some(deadline) = deadline else {
+                        break;
+                    };
+                    let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                        break;
+                    };
+                    interval.min(left)
+                };
+                if !wait_for_ack(source, ack_channel, shutdown, timeout, ack_range)? {
+                    break;
+                }
+            }
+            Ok(Batch::new(events))
+        },
+        ack_range,
+    )
+}
+
+fn ack_range<S: Source<Ack = SeqNo> + ?Sized>(source: &mut S, ack: Ack) -> Result<(), Error> {
+    ack.into_iter().try_for_each(|seq_no| source.ack(seq_no))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::Config;
+    use crate::checkpoint::{Checkpoint, MemoryStore, Store};
+    use crate::{text, Input};
+
+    #[test]
+    fn text_input() {
+        let text = b"event 1\nevent 2\nevent 3\nevent 4\nevent 5\n";
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read_batched(data_tx, ack_rx, text.as_ref(), Config::new(2));
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let mut batches = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for batch in data_rx {
+                ack_tx.send(batch.ack()).unwrap();
+                let events: Vec<_> = batch.into_iter().map(|ev| ev.raw.to_vec()).collect();
+                batches.push(events);
+            }
+        }
+        in_thread.join().unwrap();
+
+        assert_eq!(
+            batches,
+            [
+                vec![b"event 1".to_vec(), b"event 2".to_vec()],
+                vec![b"event 3".to_vec(), b"event 4".to_vec()],
+                vec![b"event 5".to_vec()],
+            ]
+        );
+    }
+
+    #[test]
+    fn checkpoint() {
+        let text = b"event 1\nevent 2\nevent 3\nevent 4\nevent 5\n";
+        let store = MemoryStore::new();
+        let mut lines = text::Lines::new(text.as_ref());
+        lines.resume(store.clone(), "text".to_string()).unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let in_thread =
+            thread::spawn(move || super::drive(&mut lines, data_tx, &ack_rx, Config::new(2)));
+
+        {
+            let ack_tx = ack_tx;
+            for batch in data_rx {
+                ack_tx.send(batch.ack()).unwrap();
+            }
+        }
+        in_thread.join().unwrap().unwrap();
+
+        let mut store = store;
+        assert_eq!(
+            store.load("text").unwrap(),
+            Some(Checkpoint {
+                offset: text.len() as u64,
+                seq_no: 5,
+                file: None
+            })
+        );
+    }
+}
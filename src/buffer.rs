@@ -0,0 +1,204 @@
+use std::io::{self, Read, Seek};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::checkpoint;
+use crate::Error;
+
+/// The number of bytes to read from an input at a time, which is also the
+/// size of the shared chunks events are sliced out of.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Moves a reader forward by a number of bytes without reading them.
+type SeekFn<R> = fn(&mut R, u64) -> Result<(), Error>;
+
+/// A read buffer from which events can be split off as `Bytes` without
+/// copying.
+///
+/// Events split off from the same chunk share its allocation, which is freed
+/// when all of them are dropped.
+pub(crate) struct ReadBuf<R> {
+    read: R,
+    buf: BytesMut,
+    spare: BytesMut, // zeroed bytes right after `buf` in the same chunk
+    seek: Option<SeekFn<R>>,
+}
+
+impl<R: Read> ReadBuf<R> {
+    pub(crate) fn new(read: R) -> Self {
+        Self {
+            read,
+            buf: BytesMut::new(),
+            spare: BytesMut::new(),
+            seek: None,
+        }
+    }
+
+    /// Lets `skip` seek instead of reading the bytes to discard.
+    pub(crate) fn set_seekable(&mut self)
+    where
+        R: Seek,
+    {
+        self.set_skip(checkpoint::seek::<R>);
+    }
+
+    /// Lets `skip` discard bytes with the given function instead of reading
+    /// them.
+    pub(crate) fn set_skip(&mut self, skip: SeekFn<R>) {
+        self.seek = Some(skip);
+    }
+
+    /// Returns the bytes read but not split off yet.
+    pub(crate) fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Reads more bytes into the buffer, and returns the number of bytes read,
+    /// which is 0 at the end of the input.
+    ///
+    /// A chunk is zeroed once when it is allocated, and then filled by as many
+    /// reads as it takes.
+    pub(crate) fn fill(&mut self) -> io::Result<usize> {
+        if self.spare.is_empty() {
+            // Starts a new chunk with the bytes not split off yet, growing it
+            // with them so that a long event is not copied too many times.
+            let len = self.buf.len();
+            let mut chunk = BytesMut::zeroed(len + CHUNK_SIZE.max(len));
+            chunk[..len].copy_from_slice(&self.buf);
+            self.buf = chunk.split_to(len);
+            self.spare = chunk;
+        }
+        let read = loop {
+            match self.read.read(&mut self.spare) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                res => break res?,
+            }
+        };
+        // Contiguous with `buf`, so no bytes are copied.
+        self.buf.unsplit(self.spare.split_to(read));
+        Ok(read)
+    }
+
+    /// Finds the end of the line that starts at `start` in the buffer, reading
+    /// more bytes as needed. Returns the index right after its newline, or
+    /// `None` if the input ends without a newline.
+    pub(crate) fn line_end(&mut self, start: usize) -> io::Result<Option<usize>> {
+        let mut pos = start;
+        loop {
+            if let Some(i) = self.buf[pos..].iter().position(|&b| b == b'\n') {
+                return Ok(Some(pos + i + 1));
+            }
+            pos = self.buf.len();
+            if self.fill()? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Splits off the first `len` bytes in the buffer.
+    pub(crate) fn split_to(&mut self, len: usize) -> Bytes {
+        self.buf.split_to(len).freeze()
+    }
+
+    /// Splits off all the bytes in the buffer.
+    pub(crate) fn split_all(&mut self) -> Bytes {
+        self.buf.split().freeze()
+    }
+
+    /// Discards the next `len` bytes of the input.
+    pub(crate) fn skip(&mut self, len: u64) -> Result<(), Error> {
+        let buffered = usize::try_from(len).map_or(self.buf.len(), |len| len.min(self.buf.len()));
+        let _ = self.buf.split_to(buffered);
+        let rest = len - buffered as u64;
+        match self.seek {
+            Some(seek) => seek(&mut self.read, rest),
+            None => checkpoint::skip(&mut self.read, rest),
+        }
+    }
+
+    /// Reads the rest of the input from `read`, keeping the bytes in the
+    /// buffer.
+    pub(crate) fn set_read(&mut self, read: R) {
+        self.read = read;
+    }
+}
+
+/// A buffer that events are copied into, so that they share larger
+/// allocations instead of having one each.
+#[cfg(feature = "ndarray")]
+#[derive(Default)]
+pub(crate) struct Arena {
+    buf: BytesMut,
+}
+
+#[cfg(feature = "ndarray")]
+impl Arena {
+    /// Starts a new event.
+    pub(crate) fn reserve(&mut self, len: usize) {
+        if self.buf.capacity() < len {
+            self.buf.reserve(CHUNK_SIZE.max(len));
+        }
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Finishes the event with the bytes added since the last call.
+    pub(crate) fn split(&mut self) -> Bytes {
+        self.buf.split().freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{ReadBuf, CHUNK_SIZE};
+
+    #[test]
+    fn lines() {
+        let mut buf = ReadBuf::new(b"line 1\nline 2\nline 3".as_ref());
+        let end = buf.line_end(0).unwrap().unwrap();
+        assert_eq!(buf.split_to(end), b"line 1\n".as_ref());
+        buf.skip(3).unwrap();
+        let end = buf.line_end(0).unwrap().unwrap();
+        assert_eq!(buf.split_to(end), b"e 2\n".as_ref());
+        assert_eq!(buf.line_end(0).unwrap(), None);
+        assert_eq!(buf.split_all(), b"line 3".as_ref());
+        assert!(buf.skip(1).is_err());
+    }
+
+    #[test]
+    fn fill() {
+        // A short read is appended in place.
+        let mut buf = ReadBuf::new(b"line 1\n".chain(b"line 2\n".as_ref()));
+        assert_eq!(buf.fill().unwrap(), 7);
+        let start = buf.buffered().as_ptr();
+        assert_eq!(buf.fill().unwrap(), 7);
+        assert_eq!(buf.buffered().as_ptr(), start);
+        assert_eq!(buf.buffered(), b"line 1\nline 2\n");
+        assert_eq!(buf.fill().unwrap(), 0);
+
+        // A line longer than a chunk is kept whole.
+        let long = vec![b'a'; CHUNK_SIZE * 3 + 1];
+        let mut buf = ReadBuf::new(long.as_slice());
+        assert_eq!(buf.line_end(0).unwrap(), None);
+        assert_eq!(buf.split_all(), long);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn arena() {
+        let mut arena = super::Arena::default();
+        arena.reserve(5);
+        arena.extend_from_slice(b"first");
+        let first = arena.split();
+        arena.reserve(6);
+        arena.extend_from_slice(b"second");
+        let second = arena.split();
+        assert_eq!(first, b"first".as_ref());
+        assert_eq!(second, b"second".as_ref());
+        assert_eq!(first.as_ptr().wrapping_add(5), second.as_ptr());
+    }
+}
@@ -0,0 +1,439 @@
+//! Persisting the progress of inputs to resume from after a restart.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AckTracker, Error, SeqNo};
+
+/// The position right after the last event of an input such that it and all
+/// the events before it have been acknowledged.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Checkpoint {
+    /// The number of bytes read from the input up to the end of the event.
+    pub offset: u64,
+    /// The sequence number of the event.
+    pub seq_no: SeqNo,
+    /// The device and inode numbers of the file `offset` is in, for an input
+    /// following a file that may be replaced by rotation.
+    #[serde(default)]
+    pub file: Option<(u64, u64)>,
+}
+
+/// A trait for a storage of checkpoints, each of which is identified by a key.
+pub trait Store {
+    /// Loads the checkpoint saved for `key`, or returns `None` if there is
+    /// none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to read the storage.
+    fn load(&mut self, key: &str) -> io::Result<Option<Checkpoint>>;
+
+    /// Saves `checkpoint` for `key`, replacing the previous one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to write to the storage.
+    fn save(&mut self, key: &str, checkpoint: Checkpoint) -> io::Result<()>;
+
+    /// Writes the checkpoints saved but held back by the storage, if any. The
+    /// default implementation does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to write to the storage.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Checkpoints kept in memory. Its clones share the same checkpoints.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    checkpoints: Arc<Mutex<HashMap<String, Checkpoint>>>,
+}
+
+impl MemoryStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn load(&mut self, key: &str) -> io::Result<Option<Checkpoint>> {
+        let checkpoints = self.checkpoints.lock().expect("not poisoned");
+        Ok(checkpoints.get(key).copied())
+    }
+
+    fn save(&mut self, key: &str, checkpoint: Checkpoint) -> io::Result<()> {
+        let mut checkpoints = self.checkpoints.lock().expect("not poisoned");
+        checkpoints.insert(key.to_string(), checkpoint);
+        Ok(())
+    }
+}
+
+/// How often `FileStore` writes its file by default.
+const DEFAULT_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Checkpoints kept in a file.
+///
+/// Every write rewrites a temporary file next to it and renames it over the
+/// file, so the file always holds a complete set of checkpoints even if the
+/// process crashes while writing.
+///
+/// Since inputs save a checkpoint as often as every ACK, the file is written
+/// at most once per interval, one second by default. A save within the
+/// interval is written by the next save after it, or by [`Store::flush`],
+/// which inputs call when they have no newer checkpoint to save, such as while
+/// they wait for more events. Dropping the store also writes it, but ignores
+/// errors. If the process crashes in between, the input resumes from an
+/// earlier checkpoint and reads some events again, but never skips one.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    checkpoints: HashMap<String, Checkpoint>,
+    interval: Duration,
+    written: Option<Instant>,
+    unwritten: bool,
+}
+
+impl FileStore {
+    /// Opens the checkpoints in the file at `path`. The file is created when a
+    /// checkpoint is saved for the first time.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read, or has invalid
+    /// contents.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let checkpoints = match File::open(&path) {
+            Ok(file) => rmp_serde::from_read(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            checkpoints,
+            interval: DEFAULT_WRITE_INTERVAL,
+            written: None,
+            unwritten: false,
+        })
+    }
+
+    /// Writes the file at most once per `interval`. `Duration::ZERO` writes
+    /// it on every save.
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut file = File::create(&tmp_path)?;
+        rmp_serde::encode::write_named(&mut file, &self.checkpoints)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.written = Some(Instant::now());
+        self.unwritten = false;
+        Ok(())
+    }
+}
+
+impl Store for FileStore {
+    fn load(&mut self, key: &str) -> io::Result<Option<Checkpoint>> {
+        Ok(self.checkpoints.get(key).copied())
+    }
+
+    fn save(&mut self, key: &str, checkpoint: Checkpoint) -> io::Result<()> {
+        self.checkpoints.insert(key.to_string(), checkpoint);
+        self.unwritten = true;
+        if self
+            .written
+            .is_some_and(|written| written.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+        self.write()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.unwritten {
+            self.write()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileStore {
+    fn drop(&mut self) {
+        if self.unwritten {
+            let _ = self.write();
+        }
+    }
+}
+
+/// A store shared by the checkpointers of multiple files in an input.
+#[derive(Clone)]
+pub(crate) struct SharedStore(Arc<Mutex<Box<dyn Store + Send>>>);
+
+impl SharedStore {
+    pub(crate) fn new<S: Store + Send + 'static>(store: S) -> Self {
+        Self(Arc::new(Mutex::new(Box::new(store))))
+    }
+}
+
+impl Store for SharedStore {
+    fn load(&mut self, key: &str) -> io::Result<Option<Checkpoint>> {
+        self.0.lock().expect("not poisoned").load(key)
+    }
+
+    fn save(&mut self, key: &str, checkpoint: Checkpoint) -> io::Result<()> {
+        self.0.lock().expect("not poisoned").save(key, checkpoint)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().expect("not poisoned").flush()
+    }
+}
+
+/// Saves the checkpoint of an input as its events are acknowledged.
+pub(crate) struct Checkpointer {
+    store: Box<dyn Store + Send>,
+    key: String,
+    acks: AckTracker<(), SeqNo>,
+    ends: BTreeMap<SeqNo, (u64, Option<(u64, u64)>)>, // offsets after outstanding events
+    file: Option<(u64, u64)>,
+}
+
+impl Checkpointer {
+    /// Loads the checkpoint for `key` from `store`, and returns it together
+    /// with a `Checkpointer` that saves later checkpoints there.
+    pub(crate) fn load<S>(mut store: S, key: String) -> Result<(Self, Checkpoint), Error>
+    where
+        S: Store + Send + 'static,
+    {
+        let checkpoint = store
+            .load(&key)
+            .map_err(|e| Error::CannotFetch(Box::new(e)))?
+            .unwrap_or_default();
+        let checkpointer = Self {
+            store: Box::new(store),
+            key,
+            acks: AckTracker::new(),
+            ends: BTreeMap::new(),
+            file: None,
+        };
+        Ok((checkpointer, checkpoint))
+    }
+
+    /// Records that the events read from now on are in `file`.
+    pub(crate) fn set_file(&mut self, file: (u64, u64)) {
+        self.file = Some(file);
+    }
+
+    /// Records that the event with `seq_no` ends at `offset`.
+    pub(crate) fn read(&mut self, seq_no: SeqNo, offset: u64) {
+        self.acks.track((), seq_no, 1);
+        self.ends.insert(seq_no, (offset, self.file));
+    }
+
+    pub(crate) fn ack(&mut self, seq_no: SeqNo) -> Result<(), Error> {
+        if self.acks.ack(&(), &seq_no) {
+            Ok(())
+        } else {
+            Err(Error::Fatal(format!("unexpected ACK for event {seq_no}")))
+        }
+    }
+
+    /// Saves the checkpoint if it has advanced, or otherwise writes the
+    /// checkpoints the store has held back.
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        let mut saved = false;
+        for ((), seq_no) in self.acks.advanced() {
+            saved = true;
+            let rest = self.ends.split_off(&(seq_no + 1));
+            let (offset, file) = self.ends[&seq_no];
+            self.ends = rest;
+            self.store
+                .save(
+                    &self.key,
+                    Checkpoint {
+                        offset,
+                        seq_no,
+                        file,
+                    },
+                )
+                .map_err(|e| Error::CannotCommit(Box::new(e)))?;
+        }
+        if !saved {
+            self.store
+                .flush()
+                .map_err(|e| Error::CannotCommit(Box::new(e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads and discards `len` bytes from `reader`, for a reader that cannot
+/// seek.
+pub(crate) fn skip<R: Read>(reader: &mut R, len: u64) -> Result<(), Error> {
+    let skipped = io::copy(&mut reader.take(len), &mut io::sink())
+        .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+    if skipped < len {
+        return Err(shorter_than_checkpoint());
+    }
+    Ok(())
+}
+
+/// Moves `reader` forward by `len` bytes without reading them.
+pub(crate) fn seek<R: Seek>(reader: &mut R, len: u64) -> Result<(), Error> {
+    let pos = reader
+        .stream_position()
+        .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+    let end = reader
+        .seek(SeekFrom::End(0))
+        .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+    let target = pos.saturating_add(len);
+    reader
+        .seek(SeekFrom::Start(target.min(end)))
+        .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+    if target > end {
+        return Err(shorter_than_checkpoint());
+    }
+    Ok(())
+}
+
+fn shorter_than_checkpoint() -> Error {
+    Error::CannotFetch(Box::new(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "input is shorter than its checkpoint",
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use std::time::Duration;
+
+    use super::{Checkpoint, Checkpointer, FileStore, MemoryStore, Store};
+
+    #[test]
+    fn file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoints");
+        let checkpoint = Checkpoint {
+            offset: 100,
+            seq_no: 3,
+            file: None,
+        };
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            assert_eq!(store.load("a").unwrap(), None);
+            store.save("a", checkpoint).unwrap();
+            store.save("b", Checkpoint::default()).unwrap();
+        }
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(store.load("a").unwrap(), Some(checkpoint));
+        assert_eq!(store.load("b").unwrap(), Some(Checkpoint::default()));
+    }
+
+    #[test]
+    fn file_store_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoints");
+        let checkpoint = Checkpoint {
+            offset: 100,
+            seq_no: 3,
+            file: None,
+        };
+        let mut store = FileStore::open(&path)
+            .unwrap()
+            .with_interval(Duration::from_secs(3600));
+        store.save("a", Checkpoint::default()).unwrap();
+        store.save("a", checkpoint).unwrap();
+        assert_eq!(
+            FileStore::open(&path).unwrap().load("a").unwrap(),
+            Some(Checkpoint::default())
+        );
+        drop(store);
+        assert_eq!(
+            FileStore::open(&path).unwrap().load("a").unwrap(),
+            Some(checkpoint)
+        );
+    }
+
+    #[test]
+    fn file_store_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoints");
+        let store = FileStore::open(&path)
+            .unwrap()
+            .with_interval(Duration::from_secs(3600));
+        let (mut checkpointer, _) = Checkpointer::load(store, "key".to_string()).unwrap();
+        let saved = || FileStore::open(&path).unwrap().load("key").unwrap();
+
+        checkpointer.read(1, 10);
+        checkpointer.read(2, 20);
+        checkpointer.ack(1).unwrap();
+        checkpointer.flush().unwrap();
+        assert_eq!(saved().map(|c| c.seq_no), Some(1));
+        checkpointer.ack(2).unwrap();
+        checkpointer.flush().unwrap();
+        assert_eq!(saved().map(|c| c.seq_no), Some(1));
+
+        // Nothing is newer, so the checkpoint held back is written.
+        checkpointer.flush().unwrap();
+        assert_eq!(saved().map(|c| c.seq_no), Some(2));
+    }
+
+    #[test]
+    fn seek() {
+        let mut reader = Cursor::new(b"0123456789".to_vec());
+        super::seek(&mut reader, 4).unwrap();
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"45");
+        super::seek(&mut reader, 4).unwrap();
+        assert!(super::seek(&mut reader, 1).is_err());
+    }
+
+    #[test]
+    fn checkpointer() {
+        let mut store = MemoryStore::new();
+        let (mut checkpointer, checkpoint) =
+            Checkpointer::load(store.clone(), "key".to_string()).unwrap();
+        assert_eq!(checkpoint, Checkpoint::default());
+
+        checkpointer.read(1, 10);
+        checkpointer.read(2, 20);
+        checkpointer.read(3, 30);
+        checkpointer.ack(2).unwrap();
+        checkpointer.flush().unwrap();
+        assert_eq!(store.load("key").unwrap(), None);
+
+        checkpointer.ack(1).unwrap();
+        checkpointer.flush().unwrap();
+        assert_eq!(
+            store.load("key").unwrap(),
+            Some(Checkpoint {
+                offset: 20,
+                seq_no: 2,
+                file: None
+            })
+        );
+        assert!(checkpointer.ack(2).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{self, Cursor, Read, Seek};
+
+/// The longest magic number to look for.
+const MAGIC_LEN: usize = 10;
+
+/// Wraps `read` with a decoder if it starts with the magic number of a
+/// compression format enabled by a cargo feature: `gzip`, `zstd`, `bzip2`, or
+/// `xz`. Otherwise, returns the input as it is.
+///
+/// Concatenated streams, such as appended gzip members, are decompressed as
+/// one.
+///
+/// # Errors
+///
+/// Returns an error if it fails to read the magic number.
+pub fn decompress<R: Read + Send + 'static>(mut read: R) -> io::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    (&mut read).take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+    let read = Cursor::new(magic.clone()).chain(read);
+    Ok(decoder(&magic, read)?.unwrap_or_else(|read| Box::new(read)))
+}
+
+/// Works the same as [`decompress`], but returns `read` itself, rewound to the
+/// start, if it is not compressed, so that it can still seek.
+pub(crate) fn decompress_seekable<R>(mut read: R) -> io::Result<Result<Box<dyn Read + Send>, R>>
+where
+    R: Read + Seek + Send + 'static,
+{
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    (&mut read).take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+    read.rewind()?;
+    decoder(&magic, read)
+}
+
+/// Wraps `read` with the decoder for the compression format `magic` starts
+/// with, or returns it back if there is none.
+#[allow(clippy::unnecessary_wraps)] // only zstd can fail
+#[cfg_attr(
+    not(any(feature = "gzip", feature = "zstd", feature = "bzip2", feature = "xz")),
+    allow(unused_variables)
+)]
+fn decoder<R: Read + Send + 'static>(
+    magic: &[u8],
+    read: R,
+) -> io::Result<Result<Box<dyn Read + Send>, R>> {
+    #[cfg(feature = "gzip")]
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Ok(Box::new(flate2::read::MultiGzDecoder::new(read))));
+    }
+    #[cfg(feature = "zstd")]
+    if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        return Ok(Ok(Box::new(zstd::stream::read::Decoder::new(read)?)));
+    }
+    #[cfg(feature = "bzip2")]
+    if is_bzip2(magic) {
+        return Ok(Ok(Box::new(bzip2::read::MultiBzDecoder::new(read))));
+    }
+    #[cfg(feature = "xz")]
+    if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        return Ok(Ok(Box::new(liblzma::read::XzDecoder::new_multi_decoder(
+            read,
+        ))));
+    }
+    Ok(Err(read))
+}
+
+/// Returns `true` if `magic` starts with a bzip2 header followed by the magic
+/// number of either a block or the end of the stream. `BZh` alone is too
+/// common at the start of a text.
+#[cfg(feature = "bzip2")]
+fn is_bzip2(magic: &[u8]) -> bool {
+    const BLOCK: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+    const END: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+    match magic {
+        [b'B', b'Z', b'h', b'1'..=b'9', rest @ ..] => {
+            rest.starts_with(&BLOCK) || rest.starts_with(&END)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::decompress;
+
+    const TEXT: &[u8] = b"event 1\nevent 2\n";
+
+    fn decompressed(data: Vec<u8>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        decompress(std::io::Cursor::new(data))
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn uncompressed() {
+        assert_eq!(decompressed(TEXT.to_vec()), TEXT);
+        assert_eq!(decompressed(b"BZ".to_vec()), b"BZ");
+        assert_eq!(decompressed(b"BZhost up\n".to_vec()), b"BZhost up\n");
+        assert_eq!(decompressed(b"BZh9 lines\n".to_vec()), b"BZh9 lines\n");
+        assert!(decompressed(Vec::new()).is_empty());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip() {
+        use std::io::Write;
+
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(TEXT).unwrap();
+            data.extend(encoder.finish().unwrap());
+        }
+        assert_eq!(decompressed(data), [TEXT, TEXT].concat());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let data = zstd::encode_all(TEXT, 0).unwrap();
+        assert_eq!(decompressed(data), TEXT);
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn bzip2() {
+        use std::io::Write;
+
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        encoder.write_all(TEXT).unwrap();
+        assert_eq!(decompressed(encoder.finish().unwrap()), TEXT);
+
+        let empty = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        assert!(decompressed(empty.finish().unwrap()).is_empty());
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz() {
+        use std::io::Write;
+
+        let mut encoder = liblzma::write::XzEncoder::new(Vec::new(), 6);
+        encoder.write_all(TEXT).unwrap();
+        assert_eq!(decompressed(encoder.finish().unwrap()), TEXT);
+    }
+}
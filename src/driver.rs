@@ -0,0 +1,341 @@
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, Select, Sender};
+
+use crate::shutdown::stop_channel;
+use crate::{Error, Shutdown};
+
+/// A trait for a data source that produces events one at a time, to be
+/// forwarded by [`drive`].
+pub trait Source {
+    type Data;
+    type Ack;
+
+    /// Fetches the next event, or returns `None` if there are no more events
+    /// at the moment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to fetch an event.
+    fn next_event(&mut self) -> Result<Option<Self::Data>, Error>;
+
+    /// Processes `Ack`, which acknowledges the receipt of a `Data` returned by
+    /// `next_event`. The default implementation discards it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `Ack` is invalid or cannot be processed.
+    fn ack(&mut self, _ack: Self::Ack) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Commits the ACKs processed so far. It is called whenever no more ACKs
+    /// are waiting to be processed, after waiting for ACKs without an event
+    /// for a poll interval, and once more before [`drive`] returns. The
+    /// default implementation does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to commit.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Returns how long to wait before calling `next_event` again after it
+    /// returns `None`, for a source that may have more events later, such as
+    /// a file being written. The default implementation returns `None`, which
+    /// means the source has ended.
+    fn poll_interval(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Sends events from `source` through `data_channel` while passing ACKs
+/// received from `ack_channel` to `source`.
+///
+/// It stops fetching events when `source` runs out of events or either channel
+/// is disconnected. It then closes `data_channel` and keeps processing ACKs
+/// until `ack_channel` is disconnected. If `source` has a poll interval, it
+/// does not run out of events; instead, `drive` waits for ACKs for the
+/// interval and tries again.
+///
+/// # Errors
+///
+/// Returns an error if `source` fails to fetch an event or to process an ACK.
+pub fn drive<S: Source + ?Sized>(
+    source: &mut S,
+    data_channel: Sender<S::Data>,
+    ack_channel: &Receiver<S::Ack>,
+) -> Result<(), Error> {
+    drive_until(source, data_channel, ack_channel, None)
+}
+
+/// Works the same as [`drive`], but also stops fetching events once
+/// `shutdown` is triggered.
+pub(crate) fn drive_until<S: Source + ?Sized>(
+    source: &mut S,
+    data_channel: Sender<S::Data>,
+    ack_channel: &Receiver<S::Ack>,
+    shutdown: Option<&Shutdown>,
+) -> Result<(), Error> {
+    forward(
+        source,
+        data_channel,
+        ack_channel,
+        shutdown,
+        |source, ack_channel| loop {
+            if let Some(event) = source.next_event()? {
+                return Ok(Some(event));
+            }
+            let Some(interval) = source.poll_interval() else {
+                return Ok(None);
+            };
+            if !wait_for_ack(source, ack_channel, shutdown, interval, S::ack)? {
+                return Ok(None);
+            }
+        },
+        S::ack,
+    )
+}
+
+/// Sends what `next` returns through `data_channel` while passing ACKs
+/// received from `ack_channel` to `ack`, as described in [`drive`], until
+/// `shutdown` is triggered.
+pub(crate) fn forward<S, D, A, N, K>(
+    source: &mut S,
+    data_channel: Sender<D>,
+    ack_channel: &Receiver<A>,
+    shutdown: Option<&Shutdown>,
+    mut next: N,
+    ack: K,
+) -> Result<(), Error>
+where
+    S: Source + ?Sized,
+    N: FnMut(&mut S, &Receiver<A>) -> Result<Option<D>, Error>,
+    K: Fn(&mut S, A) -> Result<(), Error> + Copy,
+{
+    let stop = stop_channel(shutdown);
+    let mut sel = Select::new();
+    let send_data = sel.send(&data_channel);
+    let recv_ack = sel.recv(ack_channel);
+    let recv_stop = sel.recv(&stop);
+
+    'poll: loop {
+        if shutdown.is_some_and(Shutdown::is_triggered) {
+            break;
+        }
+        let Some(data) = next(source, ack_channel)? else {
+            break;
+        };
+        loop {
+            let oper = sel.select();
+            match oper.index() {
+                i if i == send_data => {
+                    if oper.send(&data_channel, data).is_err() {
+                        // data_channel was disconnected. Exit the loop and
+                        // commit consumed.
+                        break 'poll;
+                    }
+                    break;
+                }
+                i if i == recv_ack => {
+                    let Ok(a) = oper.recv(ack_channel) else {
+                        // ack_channel was disconnected. Exit the loop and
+                        // commit consumed.
+                        break 'poll;
+                    };
+                    handle_ack(source, ack_channel, a, ack)?;
+                }
+                i if i == recv_stop => {
+                    // shutdown was triggered. Drop the event, which will be
+                    // fetched again after restart unless committed.
+                    let _ = oper.recv(&stop);
+                    break 'poll;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+    drop(sel);
+    drop(data_channel);
+    for a in ack_channel {
+        handle_ack(source, ack_channel, a, ack)?;
+    }
+    source.flush()
+}
+
+/// Waits up to `timeout` for an ACK and passes it to `ack`, or flushes
+/// `source` if none arrives. Returns `false` if `ack_channel` is disconnected
+/// or `shutdown` is triggered.
+pub(crate) fn wait_for_ack<S, A, K>(
+    source: &mut S,
+    ack_channel: &Receiver<A>,
+    shutdown: Option<&Shutdown>,
+    timeout: Duration,
+    ack: K,
+) -> Result<bool, Error>
+where
+    S: Source + ?Sized,
+    K: Fn(&mut S, A) -> Result<(), Error>,
+{
+    let stop = stop_channel(shutdown);
+    let mut sel = Select::new();
+    let recv_ack = sel.recv(ack_channel);
+    sel.recv(&stop);
+    let Ok(oper) = sel.select_timeout(timeout) else {
+        source.flush()?;
+        return Ok(true);
+    };
+    if oper.index() != recv_ack {
+        let _ = oper.recv(&stop);
+        return Ok(false);
+    }
+    let Ok(a) = oper.recv(ack_channel) else {
+        return Ok(false);
+    };
+    handle_ack(source, ack_channel, a, ack)?;
+    Ok(true)
+}
+
+fn handle_ack<S, A, K>(source: &mut S, ack_channel: &Receiver<A>, a: A, ack: K) -> Result<(), Error>
+where
+    S: Source + ?Sized,
+    K: Fn(&mut S, A) -> Result<(), Error>,
+{
+    ack(source, a)?;
+    if ack_channel.is_empty() {
+        source.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::Source;
+    use crate::{BareEvent, Error, Metadata, SeqNo, Shutdown};
+
+    /// Produces `len` events, and records ACKs and flushes.
+    struct Counter {
+        len: SeqNo,
+        next: SeqNo,
+        acked: Vec<SeqNo>,
+        flushed: usize,
+    }
+
+    impl Counter {
+        fn new(len: SeqNo) -> Self {
+            Self {
+                len,
+                next: 0,
+                acked: Vec::new(),
+                flushed: 0,
+            }
+        }
+    }
+
+    impl Source for Counter {
+        type Data = BareEvent;
+        type Ack = SeqNo;
+
+        fn next_event(&mut self) -> Result<Option<BareEvent>, Error> {
+            if self.next == self.len {
+                return Ok(None);
+            }
+            self.next += 1;
+            Ok(Some(BareEvent {
+                raw: self.next.to_string().into(),
+                seq_no: self.next,
+                meta: Metadata::default(),
+            }))
+        }
+
+        fn ack(&mut self, ack: SeqNo) -> Result<(), Error> {
+            self.acked.push(ack);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            self.flushed = self.acked.len();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drive() {
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let in_thread = thread::spawn(move || {
+            let mut source = Counter::new(5);
+            super::drive(&mut source, data_tx, &ack_rx).unwrap();
+            source
+        });
+
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for ev in data_rx {
+                ack_tx.send(ev.seq_no).unwrap();
+                events.push(ev.raw.to_vec());
+            }
+        }
+        let source = in_thread.join().unwrap();
+
+        assert_eq!(events, [b"1", b"2", b"3", b"4", b"5"]);
+        assert_eq!(source.acked, [1, 2, 3, 4, 5]);
+        assert_eq!(source.flushed, 5);
+    }
+
+    #[test]
+    fn shutdown() {
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let shutdown = Shutdown::new();
+        let token = shutdown.clone();
+        let in_thread = thread::spawn(move || {
+            let mut source = Counter::new(SeqNo::MAX);
+            super::drive_until(&mut source, data_tx, &ack_rx, Some(&token)).unwrap();
+            source
+        });
+
+        let mut received = 0;
+        {
+            let ack_tx = ack_tx;
+            for ev in data_rx {
+                if ev.seq_no == 3 {
+                    shutdown.trigger();
+                }
+                ack_tx.send(ev.seq_no).unwrap();
+                received += 1;
+            }
+        }
+        let source = in_thread.join().unwrap();
+
+        assert!(received >= 3);
+        assert_eq!(source.acked, (1..=received).collect::<Vec<_>>());
+        assert_eq!(source.flushed, received);
+    }
+
+    #[test]
+    fn data_channel_closed() {
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let in_thread = thread::spawn(move || {
+            let mut source = Counter::new(usize::MAX);
+            super::drive(&mut source, data_tx, &ack_rx).unwrap();
+            source
+        });
+
+        {
+            let ack_tx = ack_tx;
+            let ev = data_rx.recv().unwrap();
+            drop(data_rx);
+            ack_tx.send(ev.seq_no).unwrap();
+        }
+        let source = in_thread.join().unwrap();
+
+        assert_eq!(source.acked, [1]);
+        assert_eq!(source.flushed, 1);
+    }
+}
@@ -0,0 +1,637 @@
+//! Reading events from multiple files as a single stream.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+
+use crate::checkpoint::{SharedStore, Store};
+use crate::decompress::decompress_seekable;
+use crate::{batch, mbox, text, BareEvent, Error, Metadata, SeqNo, Shutdown, Source};
+
+/// An event with the path of the file it was read from.
+///
+/// Its metadata has the path as the source, and the offset in the file after
+/// decompression.
+#[derive(Debug)]
+pub struct Event {
+    pub raw: Bytes,
+    pub seq_no: SeqNo,
+    pub path: Arc<Path>,
+    pub meta: Metadata,
+}
+
+impl crate::Event for Event {
+    type Ack = SeqNo;
+
+    fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    fn ack(&self) -> Self::Ack {
+        self.seq_no
+    }
+
+    fn source(&self) -> Option<&str> {
+        self.meta.source.as_deref()
+    }
+
+    fn offset(&self) -> Option<u64> {
+        self.meta.offset
+    }
+
+    fn length(&self) -> Option<u64> {
+        self.meta.length
+    }
+
+    fn timestamp(&self) -> Option<SystemTime> {
+        self.meta.timestamp
+    }
+}
+
+/// The format of the files, which determines what an event is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// A line of a text file, as read by [`text::Input`].
+    Text,
+    /// An email in an mbox, as read by [`mbox::Input`].
+    Mbox,
+    /// A packet in a pcap or pcapng file, as read by [`crate::pcap::Input`].
+    #[cfg(feature = "pcap")]
+    Pcap,
+}
+
+/// The order in which files are read.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Order {
+    /// In the lexicographical order of their paths.
+    #[default]
+    Name,
+    /// From the least recently modified. Files modified at the same time are
+    /// ordered by their paths.
+    Modified,
+}
+
+/// Event reader for multiple files, which reads them one after another.
+///
+/// Each event has its own sequence number across all the files, which is used
+/// as its ACK. Compressed files are decompressed as described in
+/// [`decompress`](crate::decompress).
+pub struct Input {
+    data_channel: crossbeam_channel::Sender<Event>,
+    ack_channel: crossbeam_channel::Receiver<SeqNo>,
+    shutdown: Option<Shutdown>,
+    files: Files,
+}
+
+impl Input {
+    /// Creates `Input` that reads the regular files matching the glob
+    /// `pattern`, such as `/var/log/app/*.log`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `pattern` is invalid, or a matching file cannot be
+    /// accessed.
+    pub fn with_glob(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<SeqNo>,
+        pattern: &str,
+        format: Format,
+        order: Order,
+    ) -> Result<Self, Error> {
+        Ok(Self::with_files(
+            data_channel,
+            ack_channel,
+            Files::new(glob_paths(pattern)?, format, order)?,
+        ))
+    }
+
+    /// Creates `Input` that reads the regular files in `dir`. Subdirectories
+    /// are not read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` or a file in it cannot be accessed.
+    pub fn with_dir<P: AsRef<Path>>(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<SeqNo>,
+        dir: P,
+        format: Format,
+        order: Order,
+    ) -> Result<Self, Error> {
+        Ok(Self::with_files(
+            data_channel,
+            ack_channel,
+            Files::new(dir_paths(dir.as_ref())?, format, order)?,
+        ))
+    }
+
+    /// Creates `batch::Input` that reads the regular files matching the glob
+    /// `pattern` as [`Input::with_glob`] does, and sends their events in
+    /// batches, configured by `config`, through `data_channel`.
+    ///
+    /// An input that needs further configuration, such as a checkpoint, can be
+    /// created with [`Input::with_glob`] and turned into batches by
+    /// [`Input::batched`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `pattern` is invalid, or a matching file cannot be
+    /// accessed.
+    pub fn with_glob_batched(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        pattern: &str,
+        format: Format,
+        order: Order,
+        config: batch::Config,
+    ) -> Result<batch::Input<Event>, Error> {
+        Ok(batch::Input::new(
+            Box::new(Files::new(glob_paths(pattern)?, format, order)?),
+            data_channel,
+            ack_channel,
+            config,
+            None,
+        ))
+    }
+
+    /// Creates `batch::Input` that reads the regular files in `dir` as
+    /// [`Input::with_dir`] does, and sends their events in batches,
+    /// configured by `config`, through `data_channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` or a file in it cannot be accessed.
+    pub fn with_dir_batched<P: AsRef<Path>>(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        dir: P,
+        format: Format,
+        order: Order,
+        config: batch::Config,
+    ) -> Result<batch::Input<Event>, Error> {
+        Ok(batch::Input::new(
+            Box::new(Files::new(dir_paths(dir.as_ref())?, format, order)?),
+            data_channel,
+            ack_channel,
+            config,
+            None,
+        ))
+    }
+
+    fn with_files(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<SeqNo>,
+        files: Files,
+    ) -> Self {
+        Self {
+            data_channel,
+            ack_channel,
+            shutdown: None,
+            files,
+        }
+    }
+
+    /// Returns the paths of the files not opened yet, in the order they will
+    /// be read.
+    #[must_use]
+    pub fn paths(&self) -> Vec<&Path> {
+        self.files.paths.iter().map(PathBuf::as_path).collect()
+    }
+
+    /// Resumes reading each file after the checkpoint saved for its path in
+    /// `store`, and saves a new checkpoint there whenever more events in the
+    /// file are acknowledged.
+    #[must_use]
+    pub fn with_checkpoint<S>(mut self, store: S) -> Self
+    where
+        S: Store + Send + 'static,
+    {
+        self.files.store = Some(SharedStore::new(store));
+        self
+    }
+
+    /// Stops reading events once `shutdown` is triggered, as described in
+    /// [`Shutdown`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Turns the input into one that sends events in batches, configured by
+    /// `config`, through `data_channel`, and receives an ACK for each batch
+    /// from `ack_channel`. The channels given when the input was created are
+    /// dropped.
+    #[must_use]
+    pub fn batched(
+        self,
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(self.files),
+            data_channel,
+            ack_channel,
+            config,
+            self.shutdown,
+        )
+    }
+}
+
+impl super::Input for Input {
+    type Data = Event;
+    type Ack = SeqNo;
+
+    fn run(mut self) -> Result<(), Error> {
+        crate::driver::drive_until(
+            &mut self.files,
+            self.data_channel,
+            &self.ack_channel,
+            self.shutdown.as_ref(),
+        )
+    }
+}
+
+/// Returns the regular files matching the glob `pattern`.
+fn glob_paths(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    for path in glob::glob(pattern).map_err(|e| Error::CannotFetch(Box::new(e)))? {
+        let path = path.map_err(|e| Error::CannotFetch(Box::new(e)))?;
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Returns the regular files in `dir`.
+fn dir_paths(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| Error::CannotFetch(Box::new(e)))? {
+        let path = entry.map_err(|e| Error::CannotFetch(Box::new(e)))?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+type FileSource = Box<dyn Source<Data = BareEvent, Ack = SeqNo> + Send>;
+
+/// Events read from files one after another.
+struct Files {
+    format: Format,
+    paths: VecDeque<PathBuf>,
+    store: Option<SharedStore>,
+    /// Open files that are being read or have unacknowledged events.
+    readers: BTreeMap<usize, Reader>,
+    next_id: usize,
+    seq_no: SeqNo,
+    /// The file and its own sequence number of each unacknowledged event.
+    pending: HashMap<SeqNo, (usize, SeqNo)>,
+}
+
+struct Reader {
+    path: Arc<Path>,
+    name: Arc<str>, // the path as the source of events
+    source: FileSource,
+    outstanding: usize,
+    done: bool,
+}
+
+impl Files {
+    /// Returns an error if the modification time of a file cannot be read
+    /// when `order` is `Order::Modified`.
+    fn new(mut paths: Vec<PathBuf>, format: Format, order: Order) -> Result<Self, Error> {
+        paths.sort();
+        if order == Order::Modified {
+            let mut modified = HashMap::new();
+            for path in &paths {
+                let time = fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+                modified.insert(path.clone(), time);
+            }
+            paths.sort_by_key(|path| modified[path]);
+        }
+        Ok(Self {
+            format,
+            paths: paths.into(),
+            store: None,
+            readers: BTreeMap::new(),
+            next_id: 0,
+            seq_no: 0,
+            pending: HashMap::new(),
+        })
+    }
+
+    fn open(&self, path: &Path) -> Result<FileSource, Error> {
+        let file = File::open(path)
+            .and_then(decompress_seekable)
+            .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+        let key = path.to_string_lossy().into_owned();
+        let source: FileSource = match self.format {
+            Format::Text => match file {
+                Ok(decoded) => self.lines(text::Lines::new(decoded), key)?,
+                Err(file) => {
+                    let mut lines = text::Lines::new(file);
+                    lines.set_seekable();
+                    self.lines(lines, key)?
+                }
+            },
+            Format::Mbox => match file {
+                Ok(decoded) => self.emails(mbox::Emails::new(decoded)?, key)?,
+                Err(file) => {
+                    let mut emails = mbox::Emails::new(file)?;
+                    emails.set_seekable();
+                    self.emails(emails, key)?
+                }
+            },
+            #[cfg(feature = "pcap")]
+            Format::Pcap => {
+                let mut packets = match file {
+                    Ok(decoded) => crate::pcap::Packets::new(decoded)?,
+                    Err(file) => crate::pcap::Packets::with_seek(file)?,
+                };
+                if let Some(store) = &self.store {
+                    packets.resume(store.clone(), key)?;
+                }
+                Box::new(packets)
+            }
+        };
+        Ok(source)
+    }
+
+    fn lines<T>(&self, mut lines: text::Lines<T>, key: String) -> Result<FileSource, Error>
+    where
+        T: Read + Send + 'static,
+    {
+        if let Some(store) = &self.store {
+            lines.resume(store.clone(), key)?;
+        }
+        Ok(Box::new(lines))
+    }
+
+    fn emails<T>(&self, mut emails: mbox::Emails<T>, key: String) -> Result<FileSource, Error>
+    where
+        T: Read + Send + 'static,
+    {
+        if let Some(store) = &self.store {
+            emails.resume(store.clone(), key)?;
+        }
+        Ok(Box::new(emails))
+    }
+
+    /// Closes the file `id` if it has been read to the end and all its events
+    /// have been acknowledged.
+    fn close_if_done(&mut self, id: usize) -> Result<(), Error> {
+        if let Some(reader) = self.readers.get_mut(&id) {
+            if reader.done && reader.outstanding == 0 {
+                reader.source.flush()?;
+                self.readers.remove(&id);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Source for Files {
+    type Data = Event;
+    type Ack = SeqNo;
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            if let Some(mut entry) = self.readers.last_entry() {
+                let id = *entry.key();
+                let reader = entry.get_mut();
+                if !reader.done {
+                    if let Some(event) = reader.source.next_event()? {
+                        self.seq_no += 1;
+                        self.pending.insert(self.seq_no, (id, event.seq_no));
+                        reader.outstanding += 1;
+                        return Ok(Some(Event {
+                            raw: event.raw,
+                            seq_no: self.seq_no,
+                            path: reader.path.clone(),
+                            meta: Metadata {
+                                source: Some(reader.name.clone()),
+                                ..event.meta
+                            },
+                        }));
+                    }
+                    reader.done = true;
+                    self.close_if_done(id)?;
+                }
+            }
+
+            let Some(path) = self.paths.pop_front() else {
+                return Ok(None);
+            };
+            let source = self.open(&path)?;
+            self.readers.insert(
+                self.next_id,
+                Reader {
+                    name: path.to_string_lossy().into(),
+                    path: path.into(),
+                    source,
+                    outstanding: 0,
+                    done: false,
+                },
+            );
+            self.next_id += 1;
+        }
+    }
+
+    fn ack(&mut self, ack: SeqNo) -> Result<(), Error> {
+        let Some((id, seq_no)) = self.pending.remove(&ack) else {
+            return Err(Error::Fatal(format!("unexpected ACK for event {ack}")));
+        };
+        let reader = self
+            .readers
+            .get_mut(&id)
+            .expect("open until all events are acknowledged");
+        reader.source.ack(seq_no)?;
+        reader.outstanding -= 1;
+        self.close_if_done(id)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        for reader in self.readers.values_mut() {
+            reader.source.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::checkpoint::{Checkpoint, MemoryStore, Store};
+    use crate::files::{Format, Order};
+    use crate::{files, Input};
+
+    /// Reads the text files in `dir`, acknowledging the lines for which `ack`
+    /// returns `true`, and returns the file names and contents of the lines.
+    fn read(
+        dir: &Path,
+        order: Order,
+        store: Option<MemoryStore>,
+        ack: impl Fn(&str) -> bool,
+    ) -> Vec<(String, String)> {
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let mut input = files::Input::with_dir(data_tx, ack_rx, dir, Format::Text, order).unwrap();
+        if let Some(store) = store {
+            input = input.with_checkpoint(store);
+        }
+        let in_thread = thread::spawn(move || input.run().unwrap());
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for ev in data_rx {
+                let raw = String::from_utf8(ev.raw.to_vec()).unwrap();
+                if ack(&raw) {
+                    ack_tx.send(ev.seq_no).unwrap();
+                }
+                let name = ev.path.file_name().unwrap().to_string_lossy();
+                events.push((name.into_owned(), raw));
+            }
+        }
+        in_thread.join().unwrap();
+        events
+    }
+
+    fn event(name: &str, raw: &str) -> (String, String) {
+        (name.to_string(), raw.to_string())
+    }
+
+    #[test]
+    fn order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.log"), b"b 1\nb 2\n").unwrap();
+        fs::write(dir.path().join("a.log"), b"a 1\n").unwrap();
+        fs::write(dir.path().join("c.txt"), b"c 1\n").unwrap();
+        fs::create_dir(dir.path().join("d.log")).unwrap();
+
+        let events = read(dir.path(), Order::Name, None, |_| true);
+        assert_eq!(
+            events,
+            [
+                event("a.log", "a 1"),
+                event("b.log", "b 1"),
+                event("b.log", "b 2"),
+                event("c.txt", "c 1"),
+            ]
+        );
+
+        // Makes a.log the most recently modified.
+        let modified = fs::metadata(dir.path().join("c.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        File::options()
+            .append(true)
+            .open(dir.path().join("a.log"))
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        let events = read(dir.path(), Order::Modified, None, |_| true);
+        assert_eq!(events.last().unwrap(), &event("a.log", "a 1"));
+
+        let pattern = dir.path().join("*.log");
+        let (data_tx, _data_rx) = crossbeam_channel::bounded(1);
+        let (_ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = files::Input::with_glob(
+            data_tx,
+            ack_rx,
+            pattern.to_str().unwrap(),
+            Format::Text,
+            Order::Name,
+        )
+        .unwrap();
+        assert_eq!(
+            input.paths(),
+            [dir.path().join("a.log"), dir.path().join("b.log")]
+        );
+    }
+
+    #[test]
+    fn checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.log");
+        let b = dir.path().join("b.log");
+        fs::write(&a, b"a 1\na 2\n").unwrap();
+        fs::write(&b, b"b 1\nb 2\n").unwrap();
+        let mut store = MemoryStore::new();
+
+        // Acknowledges all but the second line of a.log.
+        read(dir.path(), Order::Name, Some(store.clone()), |raw| {
+            raw != "a 2"
+        });
+        let a_key = a.to_string_lossy();
+        let b_key = b.to_string_lossy();
+        assert_eq!(
+            store.load(&a_key).unwrap(),
+            Some(Checkpoint {
+                offset: 4,
+                seq_no: 1,
+                file: None
+            })
+        );
+        assert_eq!(
+            store.load(&b_key).unwrap(),
+            Some(Checkpoint {
+                offset: 8,
+                seq_no: 2,
+                file: None
+            })
+        );
+
+        // Resumes each file after its own checkpoint.
+        let events = read(dir.path(), Order::Name, Some(store.clone()), |_| true);
+        assert_eq!(events, [event("a.log", "a 2")]);
+        assert_eq!(
+            store.load(&a_key).unwrap(),
+            Some(Checkpoint {
+                offset: 8,
+                seq_no: 2,
+                file: None
+            })
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(dir.path().join("a.log.gz")).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"a 1\na 2\n").unwrap();
+        encoder.finish().unwrap();
+        fs::write(dir.path().join("b.log"), b"b 1\n").unwrap();
+
+        let events = read(dir.path(), Order::Name, None, |_| true);
+        assert_eq!(
+            events,
+            [
+                event("a.log.gz", "a 1"),
+                event("a.log.gz", "a 2"),
+                event("b.log", "b 1"),
+            ]
+        );
+    }
+}
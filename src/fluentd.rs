@@ -3,10 +3,28 @@
 //! [Fluentd Forward Protocol]:
 //! https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1
 
+mod auth;
+mod input;
+mod output;
+mod value;
+
 use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "gzip")]
+use std::io::Read;
+use std::time::{Duration, SystemTime};
 
+#[cfg(feature = "gzip")]
+use flate2::read::MultiGzDecoder;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
-use serde_bytes::ByteBuf;
+use serde_bytes::{ByteBuf, Bytes};
+
+pub use self::auth::{authenticate, Security, User};
+pub use self::input::{Event, Input};
+pub use self::output::Output;
+pub use self::value::Value;
 
 /// An array representation of pairs of time and record, used in Forward mode.
 ///
@@ -16,19 +34,544 @@ use serde_bytes::ByteBuf;
 /// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#entry
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Entry {
-    pub time: u64,
-    pub record: HashMap<String, ByteBuf>,
+    pub time: EventTime,
+    pub record: HashMap<String, Value>,
+}
+
+/// The type code of the `EventTime` extension type.
+const EVENT_TIME_EXT: i8 = 0;
+
+/// The maximum size of the entries decompressed from a compressed message,
+/// which keeps a small message from inflating without bound.
+#[cfg(feature = "gzip")]
+const MAX_DECOMPRESSED_LEN: u64 = 64 * 1024 * 1024;
+
+/// A timestamp with nanosecond precision.
+///
+/// It can be deserialized from either an integer of seconds or an [EventTime]
+/// extension type, and is always serialized as the latter.
+///
+/// [EventTime]:
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#eventtime-ext-format
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct EventTime {
+    pub seconds: u32,
+    pub nanoseconds: u32,
+}
+
+impl EventTime {
+    #[must_use]
+    pub fn new(seconds: u32, nanoseconds: u32) -> Self {
+        Self {
+            seconds,
+            nanoseconds,
+        }
+    }
+}
+
+impl From<u32> for EventTime {
+    fn from(seconds: u32) -> Self {
+        Self::new(seconds, 0)
+    }
+}
+
+impl From<EventTime> for SystemTime {
+    fn from(time: EventTime) -> Self {
+        SystemTime::UNIX_EPOCH
+            + Duration::new(time.seconds.into(), 0)
+            + Duration::from_nanos(time.nanoseconds.into())
+    }
+}
+
+impl Serialize for EventTime {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&self.seconds.to_be_bytes());
+        data[4..].copy_from_slice(&self.nanoseconds.to_be_bytes());
+        serializer.serialize_newtype_struct(
+            rmp_serde::MSGPACK_EXT_STRUCT_NAME,
+            &(EVENT_TIME_EXT, Bytes::new(&data)),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for EventTime {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(EventTimeVisitor)
+    }
+}
+
+struct EventTimeVisitor;
+
+impl<'de> Visitor<'de> for EventTimeVisitor {
+    type Value = EventTime;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an integer or an EventTime")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        u32::try_from(v)
+            .map(EventTime::from)
+            .map_err(|_| de::Error::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        u32::try_from(v)
+            .map(EventTime::from)
+            .map_err(|_| de::Error::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (ext, data): (i8, ByteBuf) = Deserialize::deserialize(deserializer)?;
+        if ext != EVENT_TIME_EXT {
+            return Err(de::Error::custom(format!(
+                "unexpected extension type: {ext}"
+            )));
+        }
+        let Ok(data) = <[u8; 8]>::try_from(data.as_slice()) else {
+            return Err(de::Error::invalid_length(data.len(), &"8 bytes"));
+        };
+        Ok(EventTime::new(
+            u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ))
+    }
 }
 
 /// A series of events packed into a single message.
 ///
-/// See [Forward Mode] in the protocol specification.
+/// It is always serialized in [Forward Mode], but can be deserialized from a
+/// message in any of the four event modes in the protocol specification:
+/// [Message Mode], [Forward Mode], [PackedForward Mode], and
+/// [CompressedPackedForward Mode].
 ///
+/// [Message Mode]:
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#message-modes
 /// [Forward Mode]:
 /// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#forward-mode
-#[derive(Debug, Deserialize, Serialize)]
+/// [PackedForward Mode]:
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#packedforward-mode
+/// [CompressedPackedForward Mode]:
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#compressedpackedforward-mode
+#[derive(Debug, Serialize)]
 pub struct ForwardMode {
     pub tag: String,
     pub entries: Vec<Entry>,
-    pub option: Option<HashMap<String, String>>,
+    pub option: Option<Options>,
+}
+
+impl<'de> Deserialize<'de> for ForwardMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(ForwardModeVisitor)
+    }
+}
+
+struct ForwardModeVisitor;
+
+impl<'de> Visitor<'de> for ForwardModeVisitor {
+    type Value = ForwardMode;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a message in one of the event modes")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let tag: String = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let events: Events = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let entries = match events {
+            Events::Message(time) => {
+                let record = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                vec![Entry { time, record }]
+            }
+            Events::Forward(entries) => entries,
+            Events::PackedForward(packed) => {
+                let option: Option<Options> = seq.next_element()?.flatten();
+                let entries = unpack(&packed, option.as_ref()).map_err(de::Error::custom)?;
+                return Ok(ForwardMode {
+                    tag,
+                    entries,
+                    option,
+                });
+            }
+        };
+        let option = seq.next_element()?.flatten();
+        Ok(ForwardMode {
+            tag,
+            entries,
+            option,
+        })
+    }
+}
+
+/// The second element of a message, which determines its event mode.
+enum Events {
+    Message(EventTime),
+    Forward(Vec<Entry>),
+    PackedForward(Vec<u8>),
+}
+
+impl<'de> Deserialize<'de> for Events {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(EventsVisitor)
+    }
+}
+
+struct EventsVisitor;
+
+impl<'de> Visitor<'de> for EventsVisitor {
+    type Value = Events;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a time, an array of entries, or a binary of packed entries")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        EventTimeVisitor.visit_u64(v).map(Events::Message)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        EventTimeVisitor.visit_i64(v).map(Events::Message)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        EventTimeVisitor
+            .visit_newtype_struct(deserializer)
+            .map(Events::Message)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut entries = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(entry) = seq.next_element()? {
+            entries.push(entry);
+        }
+        Ok(Events::Forward(entries))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Events::PackedForward(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Events::PackedForward(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Events::PackedForward(v.as_bytes().to_vec()))
+    }
+}
+
+/// Decodes a `MessagePackEventStream`, decompressing it first if `option` says
+/// so. Gzip is supported only with the `gzip` feature.
+fn unpack(packed: &[u8], option: Option<&Options>) -> Result<Vec<Entry>, String> {
+    #[cfg(feature = "gzip")]
+    let decompressed;
+    let mut stream = match option.and_then(|o| o.compressed.as_deref()) {
+        None | Some("text") => packed,
+        #[cfg(feature = "gzip")]
+        Some("gzip") => {
+            decompressed = gunzip(packed, MAX_DECOMPRESSED_LEN)?;
+            decompressed.as_slice()
+        }
+        Some(c) => return Err(format!("unsupported compression: {c}")),
+    };
+    let mut entries = Vec::new();
+    while !stream.is_empty() {
+        let entry =
+            rmp_serde::decode::from_read(&mut stream).map_err(|e| format!("invalid entry: {e}"))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Decompresses gzip members in `packed`, failing if the result would exceed
+/// `limit` bytes.
+#[cfg(feature = "gzip")]
+fn gunzip(packed: &[u8], limit: u64) -> Result<Vec<u8>, String> {
+    let mut buf = Vec::new();
+    MultiGzDecoder::new(packed)
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut buf)
+        .map_err(|e| format!("cannot decompress entries: {e}"))?;
+    if buf.len() as u64 > limit {
+        return Err(format!("decompressed entries exceed {limit} bytes"));
+    }
+    Ok(buf)
+}
+
+/// Options of a message.
+///
+/// See [Option] in the protocol specification.
+///
+/// [Option]:
+/// https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#option
+#[derive(Debug, Default, Deserialize)]
+pub struct Options {
+    /// The number of events in the message.
+    pub size: Option<usize>,
+    /// A chunk ID the client asks to be acknowledged.
+    pub chunk: Option<String>,
+    /// The compression format of packed entries, e.g. `"gzip"`, which can be
+    /// decompressed only with the `gzip` feature.
+    pub compressed: Option<String>,
+}
+
+impl Serialize for Options {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = usize::from(self.size.is_some())
+            + usize::from(self.chunk.is_some())
+            + usize::from(self.compressed.is_some());
+        let mut map = serializer.serialize_map(Some(len))?;
+        if let Some(size) = self.size {
+            map.serialize_entry("size", &size)?;
+        }
+        if let Some(chunk) = &self.chunk {
+            map.serialize_entry("chunk", chunk)?;
+        }
+        if let Some(compressed) = &self.compressed {
+            map.serialize_entry("compressed", compressed)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    use serde::Serialize;
+    use serde_bytes::Bytes;
+
+    use super::{Entry, EventTime, ForwardMode, Options, Value};
+
+    fn record(message: &[u8]) -> HashMap<String, Value> {
+        let mut record = HashMap::new();
+        record.insert("message".to_string(), Value::from(message));
+        record
+    }
+
+    fn packed_entries() -> Vec<u8> {
+        let mut packed = Vec::new();
+        for (time, message) in [(1, b"event 1"), (2, b"event 2")] {
+            let time = EventTime::new(time, 0);
+            rmp_serde::encode::write(&mut packed, &(time, record(message))).unwrap();
+        }
+        packed
+    }
+
+    fn messages(msg: &ForwardMode) -> Vec<(u32, Vec<u8>)> {
+        msg.entries
+            .iter()
+            .map(|e| {
+                let message = e.record["message"].as_bytes().unwrap_or_default();
+                (e.time.seconds, message.to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn event_time() {
+        let time = EventTime::new(0x0102_0304, 0x0506_0708);
+        let buf = rmp_serde::to_vec(&time).unwrap();
+        assert_eq!(buf, b"\xd7\x00\x01\x02\x03\x04\x05\x06\x07\x08");
+        assert_eq!(rmp_serde::from_slice::<EventTime>(&buf).unwrap(), time);
+
+        let buf = rmp_serde::to_vec(&1_u32).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<EventTime>(&buf).unwrap(),
+            EventTime::new(1, 0)
+        );
+
+        let buf = rmp_serde::to_vec(&u64::MAX).unwrap();
+        assert!(rmp_serde::from_slice::<EventTime>(&buf).is_err());
+    }
+
+    #[test]
+    fn event_time_to_system_time() {
+        let time = SystemTime::from(EventTime::new(1_700_000_000, 500));
+        assert_eq!(
+            time.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::new(1_700_000_000, 500)
+        );
+    }
+
+    #[test]
+    fn message_mode_with_event_time() {
+        let time = EventTime::new(1, 500);
+        let buf = rmp_serde::to_vec(&("tag", time, record(b"event 1"))).unwrap();
+        let msg: ForwardMode = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(msg.entries.len(), 1);
+        assert_eq!(msg.entries[0].time, time);
+    }
+
+    #[test]
+    fn typed_record() {
+        #[derive(Serialize)]
+        struct Record {
+            log: &'static str,
+            status: i32,
+            ratio: f64,
+            ok: bool,
+            kubernetes: HashMap<&'static str, &'static str>,
+        }
+
+        let mut kubernetes = HashMap::new();
+        kubernetes.insert("pod_name", "pod");
+        let record = Record {
+            log: "text",
+            status: -1,
+            ratio: 0.5,
+            ok: true,
+            kubernetes,
+        };
+        let mut buf = Vec::new();
+        ("tag", 1_u32, record)
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf).with_struct_map())
+            .unwrap();
+        let msg: ForwardMode = rmp_serde::from_slice(&buf).unwrap();
+        let record = &msg.entries[0].record;
+        assert_eq!(record["log"], Value::from("text"));
+        assert_eq!(record["status"], Value::Int(-1));
+        assert_eq!(record["ratio"], Value::Float(0.5));
+        assert_eq!(record["ok"], Value::Boolean(true));
+        assert_eq!(
+            record["kubernetes"],
+            Value::Map(vec![(Value::from("pod_name"), Value::from("pod"))])
+        );
+    }
+
+    #[test]
+    fn message_mode() {
+        let buf = rmp_serde::to_vec(&("tag", 1_u64, record(b"event 1"))).unwrap();
+        let msg: ForwardMode = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(msg.tag, "tag");
+        assert_eq!(messages(&msg), [(1, b"event 1".to_vec())]);
+        assert!(msg.option.is_none());
+    }
+
+    #[test]
+    fn forward_mode() {
+        let msg = ForwardMode {
+            tag: "tag".to_string(),
+            entries: vec![
+                Entry {
+                    time: 1.into(),
+                    record: record(b"event 1"),
+                },
+                Entry {
+                    time: 2.into(),
+                    record: record(b"event 2"),
+                },
+            ],
+            option: Some(Options {
+                size: Some(2),
+                ..Options::default()
+            }),
+        };
+        let buf = rmp_serde::to_vec(&msg).unwrap();
+        let msg: ForwardMode = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(msg.tag, "tag");
+        assert_eq!(
+            messages(&msg),
+            [(1, b"event 1".to_vec()), (2, b"event 2".to_vec())]
+        );
+        assert_eq!(msg.option.unwrap().size, Some(2));
+    }
+
+    #[test]
+    fn packed_forward_mode() {
+        let packed = packed_entries();
+        let buf = rmp_serde::to_vec(&("tag", Bytes::new(&packed))).unwrap();
+        let msg: ForwardMode = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(
+            messages(&msg),
+            [(1, b"event 1".to_vec()), (2, b"event 2".to_vec())]
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn compressed_packed_forward_mode() {
+        use std::io::Write;
+
+        use flate2::{write::GzEncoder, Compression};
+
+        #[derive(Serialize)]
+        struct CompressedOption {
+            compressed: &'static str,
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&packed_entries()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let option = CompressedOption { compressed: "gzip" };
+        let mut buf = Vec::new();
+        ("tag", Bytes::new(&compressed), option)
+            .serialize(&mut rmp_serde::Serializer::new(&mut buf).with_struct_map())
+            .unwrap();
+        let msg: ForwardMode = rmp_serde::from_slice(&buf).unwrap();
+        assert_eq!(
+            messages(&msg),
+            [(1, b"event 1".to_vec()), (2, b"event 2".to_vec())]
+        );
+        assert_eq!(msg.option.unwrap().compressed.as_deref(), Some("gzip"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decompression_limit() {
+        use std::io::Write;
+
+        use flate2::{write::GzEncoder, Compression};
+
+        let entries = packed_entries();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&entries).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let len = entries.len() as u64;
+        assert_eq!(super::gunzip(&compressed, len).unwrap(), entries);
+        assert!(super::gunzip(&compressed, len - 1).is_err());
+    }
+
+    #[test]
+    fn unsupported_compression() {
+        let mut option = HashMap::new();
+        option.insert("compressed", "zip");
+        let buf = rmp_serde::to_vec(&("tag", Bytes::new(&packed_entries()), option)).unwrap();
+        assert!(rmp_serde::from_slice::<ForwardMode>(&buf).is_err());
+    }
 }
@@ -0,0 +1,322 @@
+//! The handshake phase of the Forward Protocol.
+//!
+//! See [Handshake Messages] in the protocol specification.
+//!
+//! [Handshake Messages]:
+//! https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1#handshake-messages
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use serde::{Deserialize, Serialize};
+use serde_bytes::{ByteBuf, Bytes};
+use sha2::{Digest, Sha512};
+
+const SALT_LEN: usize = 16;
+
+/// Settings for shared-key authentication in the handshake phase.
+#[derive(Clone, Debug, Default)]
+pub struct Security {
+    /// The hostname sent to the peer.
+    pub self_hostname: String,
+    /// The key shared by the server and its clients.
+    pub shared_key: String,
+    /// For a server, the users allowed to connect; user authentication is
+    /// disabled if empty. For a client, the first user is used to log in if
+    /// the server requires user authentication.
+    pub users: Vec<User>,
+}
+
+/// A username and password pair for user authentication.
+#[derive(Clone, Debug)]
+pub struct User {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct HeloOptions {
+    nonce: ByteBuf,
+    #[serde(default)]
+    auth: ByteBuf,
+    #[serde(default)]
+    keepalive: bool,
+}
+
+/// Performs the client side of the handshake on `stream`.
+///
+/// # Errors
+///
+/// Returns an error if the server rejects the client, the server cannot prove
+/// that it knows the shared key, or the connection fails.
+pub fn authenticate(stream: &mut TcpStream, security: &Security) -> io::Result<()> {
+    let salt = random_salt()?;
+    let mut reader: &TcpStream = stream;
+    let mut writer: &TcpStream = stream;
+    connect(&mut reader, &mut writer, security, &salt)
+}
+
+/// Performs the server side of the handshake, rejecting a client that fails
+/// to authenticate.
+pub(super) fn accept<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    security: &Security,
+) -> io::Result<()> {
+    let nonce = random_salt()?;
+    let auth_salt = if security.users.is_empty() {
+        Vec::new()
+    } else {
+        random_salt()?
+    };
+    accept_with(reader, writer, security, &nonce, &auth_salt)
+}
+
+fn accept_with<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    security: &Security,
+    nonce: &[u8],
+    auth_salt: &[u8],
+) -> io::Result<()> {
+    let helo = HeloOptions {
+        nonce: ByteBuf::from(nonce),
+        auth: ByteBuf::from(auth_salt),
+        keepalive: true,
+    };
+    rmp_serde::encode::write_named(writer, &("HELO", helo)).map_err(invalid_data)?;
+    writer.flush()?;
+
+    let (kind, hostname, salt, digest, username, password_digest): (
+        String,
+        String,
+        ByteBuf,
+        String,
+        String,
+        String,
+    ) = rmp_serde::from_read(&mut *reader).map_err(invalid_data)?;
+    if kind != "PING" {
+        return Err(invalid_data(format!("expected PING, got {kind}")));
+    }
+
+    let expected = hexdigest(&[&salt, hostname.as_bytes(), nonce, key(security)]);
+    let reason = if !digest_eq(&digest, &expected) {
+        Some("shared_key mismatch")
+    } else if !security.users.is_empty()
+        && !security.users.iter().any(|user| {
+            user.username == username
+                && digest_eq(
+                    &password_digest,
+                    &hexdigest(&[
+                        auth_salt,
+                        user.username.as_bytes(),
+                        user.password.as_bytes(),
+                    ]),
+                )
+        })
+    {
+        Some("username/password mismatch")
+    } else {
+        None
+    };
+    if let Some(reason) = reason {
+        rmp_serde::encode::write(writer, &("PONG", false, reason, "", "")).map_err(invalid_data)?;
+        writer.flush()?;
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+    }
+
+    let digest = hexdigest(&[
+        &salt,
+        security.self_hostname.as_bytes(),
+        nonce,
+        key(security),
+    ]);
+    rmp_serde::encode::write(writer, &("PONG", true, "", &security.self_hostname, digest))
+        .map_err(invalid_data)?;
+    writer.flush()
+}
+
+fn connect<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    security: &Security,
+    salt: &[u8],
+) -> io::Result<()> {
+    let (kind, helo): (String, HeloOptions) =
+        rmp_serde::from_read(&mut *reader).map_err(invalid_data)?;
+    if kind != "HELO" {
+        return Err(invalid_data(format!("expected HELO, got {kind}")));
+    }
+
+    let hostname = security.self_hostname.as_bytes();
+    let digest = hexdigest(&[salt, hostname, &helo.nonce, key(security)]);
+    let (username, password_digest) = match security.users.first() {
+        Some(user) if !helo.auth.is_empty() => (
+            user.username.as_str(),
+            hexdigest(&[
+                &helo.auth,
+                user.username.as_bytes(),
+                user.password.as_bytes(),
+            ]),
+        ),
+        _ => ("", String::new()),
+    };
+    rmp_serde::encode::write(
+        writer,
+        &(
+            "PING",
+            &security.self_hostname,
+            Bytes::new(salt),
+            digest,
+            username,
+            password_digest,
+        ),
+    )
+    .map_err(invalid_data)?;
+    writer.flush()?;
+
+    let (kind, authenticated, reason, server_hostname, digest): (
+        String,
+        bool,
+        String,
+        String,
+        String,
+    ) = rmp_serde::from_read(&mut *reader).map_err(invalid_data)?;
+    if kind != "PONG" {
+        return Err(invalid_data(format!("expected PONG, got {kind}")));
+    }
+    if !authenticated {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+    }
+    let expected = hexdigest(&[salt, server_hostname.as_bytes(), &helo.nonce, key(security)]);
+    if !digest_eq(&digest, &expected) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "shared_key mismatch",
+        ));
+    }
+    Ok(())
+}
+
+fn key(security: &Security) -> &[u8] {
+    security.shared_key.as_bytes()
+}
+
+/// Returns the hexadecimal SHA-512 digest of the concatenation of `parts`.
+fn hexdigest(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hex(&hasher.finalize())
+}
+
+/// Compares digests in constant time, so that the time taken does not reveal
+/// how many leading characters match.
+fn digest_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+pub(super) fn random_salt() -> io::Result<Vec<u8>> {
+    let mut salt = vec![0; SALT_LEN];
+    getrandom::getrandom(&mut salt).map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(salt)
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{Security, User};
+
+    const NONCE: &[u8] = b"0123456789abcdef";
+    const AUTH_SALT: &[u8] = b"fedcba9876543210";
+    const SALT: &[u8] = b"salt salt salt!!";
+
+    fn security(shared_key: &str, users: &[(&str, &str)]) -> Security {
+        Security {
+            self_hostname: "host".to_string(),
+            shared_key: shared_key.to_string(),
+            users: users
+                .iter()
+                .map(|&(username, password)| User {
+                    username: username.to_string(),
+                    password: password.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn handshake(server: Security, client: &Security) -> (io::Result<()>, io::Result<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let auth_salt = if server.users.is_empty() {
+                b""
+            } else {
+                AUTH_SALT
+            };
+            let mut reader = BufReader::new(&stream);
+            super::accept_with(&mut reader, &mut &stream, &server, NONCE, auth_salt)
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        let client = super::connect(&mut &stream, &mut &stream, client, SALT);
+        (server.join().unwrap(), client)
+    }
+
+    #[test]
+    fn shared_key() {
+        let (server, client) = handshake(security("key", &[]), &security("key", &[]));
+        assert!(server.is_ok());
+        assert!(client.is_ok());
+    }
+
+    #[test]
+    fn shared_key_mismatch() {
+        let (server, client) = handshake(security("key", &[]), &security("wrong", &[]));
+        assert_eq!(server.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(client.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn user_auth() {
+        let server = security("key", &[("alice", "secret"), ("bob", "password")]);
+        let (server_res, client) =
+            handshake(server.clone(), &security("key", &[("bob", "password")]));
+        assert!(server_res.is_ok());
+        assert!(client.is_ok());
+
+        let (server_res, client) =
+            handshake(server.clone(), &security("key", &[("bob", "secret")]));
+        assert!(server_res.is_err());
+        assert!(client.is_err());
+
+        let (server_res, client) = handshake(server, &security("key", &[]));
+        assert!(server_res.is_err());
+        assert!(client.is_err());
+    }
+}
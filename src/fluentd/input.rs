@@ -0,0 +1,870 @@
+//! Receiving events from Fluentd forwarders over TCP.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufReader};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+    UdpSocket,
+};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use super::{auth, Entry, ForwardMode, Security, Value};
+use crate::shutdown::stop_channel;
+use crate::{Error, SeqNo};
+
+/// An event included in a Forward Protocol message.
+#[derive(Debug)]
+pub struct Event {
+    pub tag: String,
+    pub entry: Entry,
+    pub seq_no: SeqNo,
+}
+
+impl crate::Event for Event {
+    type Ack = SeqNo;
+
+    fn raw(&self) -> &[u8] {
+        self.entry
+            .record
+            .get("message")
+            .and_then(Value::as_bytes)
+            .unwrap_or_default()
+    }
+
+    fn ack(&self) -> Self::Ack {
+        self.seq_no
+    }
+
+    fn source(&self) -> Option<&str> {
+        Some(&self.tag)
+    }
+
+    fn timestamp(&self) -> Option<SystemTime> {
+        Some(self.entry.time.into())
+    }
+}
+
+/// Event reader for Fluentd forwarders, acting as a Forward Protocol server.
+pub struct Input {
+    data_channel: Option<crossbeam_channel::Sender<Event>>,
+    ack_channel: crossbeam_channel::Receiver<SeqNo>,
+    listener: TcpListener,
+    security: Option<Arc<Security>>,
+    heartbeat: bool,
+    shutdown: Option<crate::Shutdown>,
+}
+
+impl Input {
+    /// Creates `Input` that listens on `addr` for forwarders.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to bind to `addr`.
+    pub fn new<A: ToSocketAddrs>(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<SeqNo>,
+        addr: A,
+    ) -> io::Result<Self> {
+        Ok(Self::with_listener(
+            data_channel,
+            ack_channel,
+            TcpListener::bind(addr)?,
+        ))
+    }
+
+    /// Creates `Input` that accepts forwarders from `listener`.
+    #[must_use]
+    pub fn with_listener(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<SeqNo>,
+        listener: TcpListener,
+    ) -> Self {
+        Self {
+            data_channel: Some(data_channel),
+            ack_channel,
+            listener,
+            security: None,
+            heartbeat: true,
+            shutdown: None,
+        }
+    }
+
+    /// Stops responding to UDP heartbeats from forwarders.
+    ///
+    /// By default, `Input` binds a UDP socket to the same address as its TCP
+    /// listener and answers heartbeats on it, so that senders checking the
+    /// health of their peers with UDP heartbeats can tell it is alive.
+    #[must_use]
+    pub fn without_heartbeat(mut self) -> Self {
+        self.heartbeat = false;
+        self
+    }
+
+    /// Requires forwarders to authenticate themselves in the handshake phase
+    /// before sending messages. Connections from forwarders that fail to
+    /// authenticate are closed.
+    #[must_use]
+    pub fn with_security(mut self, security: Security) -> Self {
+        self.security = Some(Arc::new(security));
+        self
+    }
+
+    /// Stops accepting messages once `shutdown` is triggered, as described in
+    /// [`Shutdown`](crate::Shutdown). Forwarders are disconnected, and
+    /// messages not fully acknowledged are not answered, so forwarders
+    /// waiting for answers send them again to another server.
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: crate::Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Returns the local address this input is listening on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying socket fails to report its address.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl crate::Input for Input {
+    type Data = Event;
+    type Ack = SeqNo;
+
+    /// Accepts forwarders and sends the entries of the messages they send
+    /// through `data_channel`.
+    ///
+    /// It keeps running until either `data_channel` or `ack_channel` is
+    /// closed, or its shutdown token is triggered. A connection sending an
+    /// invalid message is closed without affecting the others. If a message
+    /// has the `chunk` option, its `ack` response is sent once all its entries
+    /// have been acknowledged through `ack_channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to bind a UDP socket for heartbeats, fails
+    /// to accept a connection, or receives a duplicate ACK for an entry of a
+    /// message with the `chunk` option.
+    fn run(mut self) -> Result<(), Error> {
+        let Some(data_channel) = &self.data_channel else {
+            return Err(Error::ChannelClosed);
+        };
+        let local_addr = self
+            .listener
+            .local_addr()
+            .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+        let conns = Arc::new(Mutex::new(Connections::default()));
+        let (msg_tx, msg_rx) = crossbeam_channel::bounded(1);
+        let (acceptor, responder) =
+            spawn(self.listener, self.security, self.heartbeat, &conns, msg_tx)?;
+
+        let stop = stop_channel(self.shutdown.as_ref());
+        let mut idle = crossbeam_channel::Select::new();
+        let recv_msg = idle.recv(&msg_rx);
+        let idle_ack = idle.recv(&self.ack_channel);
+        let idle_stop = idle.recv(&stop);
+        let mut sel = crossbeam_channel::Select::new();
+        let send_data = sel.send(data_channel);
+        let recv_ack = sel.recv(&self.ack_channel);
+        let recv_stop = sel.recv(&stop);
+        let mut seq_no = 0;
+        let mut chunks = Chunks::default();
+        let mut result = Ok(());
+
+        'poll: loop {
+            let oper = idle.select();
+            let (conn, msg) = match oper.index() {
+                i if i == recv_msg => match oper.recv(&msg_rx) {
+                    Ok(Received::Message(conn, msg)) => (conn, msg),
+                    Ok(Received::AcceptFailed(e)) => {
+                        result = Err(Error::CannotFetch(Box::new(e)));
+                        break 'poll;
+                    }
+                    Err(_) => break 'poll,
+                },
+                i if i == idle_ack => {
+                    let Ok(ack) = oper.recv(&self.ack_channel) else {
+                        // ack_channel was disconnected. Exit the loop and
+                        // close all connections.
+                        break 'poll;
+                    };
+                    if let Err(e) = chunks.ack(ack, &conns) {
+                        result = Err(e);
+                        break 'poll;
+                    }
+                    continue;
+                }
+                i if i == idle_stop => {
+                    let _ = oper.recv(&stop);
+                    break 'poll;
+                }
+                _ => unreachable!(),
+            };
+            chunks.insert(conn, seq_no + 1, &msg, &conns);
+            for entry in msg.entries {
+                seq_no += 1;
+                loop {
+                    let oper = sel.select();
+                    match oper.index() {
+                        i if i == send_data => {
+                            let event = Event {
+                                tag: msg.tag.clone(),
+                                entry,
+                                seq_no,
+                            };
+                            if oper.send(data_channel, event).is_err() {
+                                // data_channel was disconnected. Exit the
+                                // loop and close all connections.
+                                break 'poll;
+                            }
+                            break;
+                        }
+                        i if i == recv_ack => {
+                            let Ok(ack) = oper.recv(&self.ack_channel) else {
+                                // ack_channel was disconnected. Exit the
+                                // loop and close all connections.
+                                break 'poll;
+                            };
+                            if let Err(e) = chunks.ack(ack, &conns) {
+                                result = Err(e);
+                                break 'poll;
+                            }
+                        }
+                        i if i == recv_stop => {
+                            let _ = oper.recv(&stop);
+                            break 'poll;
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            }
+        }
+        self.data_channel = None;
+        for ack in &self.ack_channel {
+            if let Err(e) = chunks.ack(ack, &conns) {
+                result = result.and(Err(e));
+                break;
+            }
+        }
+
+        drop(msg_rx);
+        close(&conns, local_addr, responder.is_some());
+        join(acceptor, responder)?;
+        result
+    }
+}
+
+/// Spawns the connection acceptor, and the heartbeat responder if `heartbeat`
+/// is set.
+fn spawn(
+    listener: TcpListener,
+    security: Option<Arc<Security>>,
+    heartbeat: bool,
+    conns: &Arc<Mutex<Connections>>,
+    msg_tx: crossbeam_channel::Sender<Received>,
+) -> Result<(JoinHandle<()>, Option<JoinHandle<()>>), Error> {
+    let responder = if heartbeat {
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+        let socket = UdpSocket::bind(local_addr).map_err(|e| Error::CannotFetch(Box::new(e)))?;
+        let conns = conns.clone();
+        Some(thread::spawn(move || {
+            respond_to_heartbeats(&socket, &conns);
+        }))
+    } else {
+        None
+    };
+    let conns = conns.clone();
+    let acceptor = thread::spawn(move || accept(&listener, security.as_ref(), &conns, &msg_tx));
+    Ok((acceptor, responder))
+}
+
+/// Waits for the connection acceptor and the heartbeat responder to finish.
+fn join(acceptor: JoinHandle<()>, responder: Option<JoinHandle<()>>) -> Result<(), Error> {
+    acceptor
+        .join()
+        .map_err(|_| Error::Fatal("connection acceptor panicked".to_string()))?;
+    if let Some(responder) = responder {
+        responder
+            .join()
+            .map_err(|_| Error::Fatal("heartbeat responder panicked".to_string()))?;
+    }
+    Ok(())
+}
+
+/// What a connection thread passes on to `Input::run`.
+enum Received {
+    Message(u64, ForwardMode),
+    AcceptFailed(io::Error),
+}
+
+/// How long a response to a client may block before the connection is
+/// closed.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of responses that can wait to be written to a client. Further
+/// responses are dropped, and the client resends their chunks.
+const PENDING_RESPONSES: usize = 64;
+
+/// How long a client may take to send each handshake message before its
+/// connection is closed.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The connections accepted so far.
+#[derive(Default)]
+struct Connections {
+    closed: bool,
+    streams: HashMap<u64, Connection>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    /// The chunk IDs to send `ack` responses for.
+    responses: crossbeam_channel::Sender<String>,
+}
+
+/// Accepts connections from forwarders until `close` is called, spawning a
+/// thread reading messages, and one writing responses, for each connection.
+fn accept(
+    listener: &TcpListener,
+    security: Option<&Arc<Security>>,
+    conns: &Arc<Mutex<Connections>>,
+    msg_tx: &crossbeam_channel::Sender<Received>,
+) {
+    let mut readers = Vec::new();
+    let mut next_id = 0;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                let _ = msg_tx.send(Received::AcceptFailed(e));
+                break;
+            }
+        };
+        let id = next_id;
+        next_id += 1;
+        let (response_tx, response_rx) = crossbeam_channel::bounded(PENDING_RESPONSES);
+        let writer = {
+            let mut conns = conns.lock().expect("not poisoned");
+            if conns.closed {
+                break;
+            }
+            let (Ok(clone), Ok(writer)) = (stream.try_clone(), stream.try_clone()) else {
+                continue;
+            };
+            if writer.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+                continue;
+            }
+            conns.streams.insert(
+                id,
+                Connection {
+                    stream: clone,
+                    responses: response_tx,
+                },
+            );
+            writer
+        };
+        let conns = conns.clone();
+        let msg_tx = msg_tx.clone();
+        let security = security.cloned();
+        readers.retain(|r: &thread::JoinHandle<()>| !r.is_finished());
+        readers.push(thread::spawn(move || {
+            let writer = thread::spawn(move || write_responses(writer, &response_rx));
+            read_messages(id, &stream, security.as_deref(), &msg_tx);
+            // Dropping the sender of the responses stops the writer.
+            conns.lock().expect("not poisoned").streams.remove(&id);
+            let _ = writer.join();
+        }));
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+}
+
+/// Reads messages from `stream` until the connection is closed or an invalid
+/// message is received. If `security` is given, the client should complete
+/// the handshake first, sending each message within `HANDSHAKE_TIMEOUT`.
+fn read_messages(
+    id: u64,
+    stream: &TcpStream,
+    security: Option<&Security>,
+    msg_tx: &crossbeam_channel::Sender<Received>,
+) {
+    let mut reader = BufReader::new(stream);
+    if let Some(security) = security {
+        if stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).is_err()
+            || auth::accept(&mut reader, &mut &*stream, security).is_err()
+            || stream.set_read_timeout(None).is_err()
+        {
+            return;
+        }
+    }
+    let mut de = rmp_serde::Deserializer::new(reader);
+    while let Ok(msg) = ForwardMode::deserialize(&mut de) {
+        if msg_tx.send(Received::Message(id, msg)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Messages with the `chunk` option whose entries have not all been
+/// acknowledged yet, keyed by the sequence number of their first entry.
+#[derive(Default)]
+struct Chunks {
+    pending: BTreeMap<SeqNo, Chunk>,
+}
+
+struct Chunk {
+    id: String,
+    conn: u64,
+    acked: Vec<bool>,
+    remaining: usize,
+}
+
+impl Chunks {
+    /// Starts tracking `msg` from connection `conn`, whose entries are
+    /// numbered from `first`.
+    fn insert(&mut self, conn: u64, first: SeqNo, msg: &ForwardMode, conns: &Mutex<Connections>) {
+        let Some(id) = msg.option.as_ref().and_then(|o| o.chunk.clone()) else {
+            return;
+        };
+        if msg.entries.is_empty() {
+            respond(conns, conn, id);
+            return;
+        }
+        self.pending.insert(
+            first,
+            Chunk {
+                id,
+                conn,
+                acked: vec![false; msg.entries.len()],
+                remaining: msg.entries.len(),
+            },
+        );
+    }
+
+    /// Marks the entry `seq_no` as acknowledged, and sends the `ack` response
+    /// if it was the last one in its chunk. Returns an error if the entry has
+    /// already been acknowledged.
+    fn ack(&mut self, seq_no: SeqNo, conns: &Mutex<Connections>) -> Result<(), Error> {
+        let Some((&first, chunk)) = self.pending.range_mut(..=seq_no).next_back() else {
+            return Ok(());
+        };
+        let Some(acked) = chunk.acked.get_mut(seq_no - first) else {
+            return Ok(());
+        };
+        if *acked {
+            return Err(Error::Fatal(format!("duplicate ACK for {seq_no}")));
+        }
+        *acked = true;
+        chunk.remaining -= 1;
+        if chunk.remaining == 0 {
+            let chunk = self.pending.remove(&first).expect("exists");
+            respond(conns, chunk.conn, chunk.id);
+        }
+        Ok(())
+    }
+}
+
+/// Queues the `ack` response for `chunk` to connection `conn`, if it is still
+/// open. The response is dropped if too many responses to the connection are
+/// waiting already, which happens when the client stops reading, so that it
+/// does not hold up the other connections. The client will resend the chunk.
+fn respond(conns: &Mutex<Connections>, conn: u64, chunk: String) {
+    let conns = conns.lock().expect("not poisoned");
+    if let Some(conn) = conns.streams.get(&conn) {
+        let _ = conn.responses.try_send(chunk);
+    }
+}
+
+/// Writes the `ack` response for each chunk ID received from `responses` to
+/// `stream`. If a write fails or times out, the connection is closed, since
+/// the response may have been written partially.
+fn write_responses(mut stream: TcpStream, responses: &crossbeam_channel::Receiver<String>) {
+    #[derive(Serialize)]
+    struct Response<'a> {
+        ack: &'a str,
+    }
+
+    for chunk in responses {
+        if rmp_serde::encode::write_named(&mut stream, &Response { ack: &chunk }).is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+            break;
+        }
+    }
+}
+
+/// Answers each UDP heartbeat with a single null byte, as Fluentd does, until
+/// `close` is called.
+fn respond_to_heartbeats(socket: &UdpSocket, conns: &Mutex<Connections>) {
+    let mut buf = [0; 128];
+    loop {
+        let received = socket.recv_from(&mut buf);
+        if conns.lock().expect("not poisoned").closed {
+            break;
+        }
+        if let Ok((_, peer)) = received {
+            let _ = socket.send_to(b"\0", peer);
+        }
+    }
+}
+
+/// Closes all connections and wakes up the acceptor, and the heartbeat
+/// responder if any, so that they can stop.
+fn close(conns: &Mutex<Connections>, local_addr: SocketAddr, heartbeat: bool) {
+    {
+        let mut conns = conns.lock().expect("not poisoned");
+        conns.closed = true;
+        for conn in conns.streams.values() {
+            let _ = conn.stream.shutdown(Shutdown::Both);
+        }
+    }
+    let wake_addr = match local_addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), local_addr.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), local_addr.port())
+        }
+        _ => local_addr,
+    };
+    let _ = TcpStream::connect(wake_addr);
+    if heartbeat {
+        let unspecified: IpAddr = if wake_addr.is_ipv4() {
+            Ipv4Addr::UNSPECIFIED.into()
+        } else {
+            Ipv6Addr::UNSPECIFIED.into()
+        };
+        if let Ok(socket) = UdpSocket::bind((unspecified, 0)) {
+            let _ = socket.send_to(&[], wake_addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{ErrorKind, Read};
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::fluentd::{self, Entry, ForwardMode, Options, Security, Value};
+    use crate::{Error, Input, Shutdown};
+
+    fn forward(stream: &mut TcpStream, tag: &str, messages: &[&[u8]], chunk: Option<&str>) {
+        let entries = messages
+            .iter()
+            .map(|&m| {
+                let mut record = HashMap::new();
+                record.insert("message".to_string(), Value::from(m));
+                Entry {
+                    time: 1.into(),
+                    record,
+                }
+            })
+            .collect();
+        let msg = ForwardMode {
+            tag: tag.to_string(),
+            entries,
+            option: chunk.map(|chunk| Options {
+                chunk: Some(chunk.to_string()),
+                ..Options::default()
+            }),
+        };
+        rmp_serde::encode::write(stream, &msg).unwrap();
+    }
+
+    #[test]
+    fn forward_input() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener);
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let mut client1 = TcpStream::connect(addr).unwrap();
+        let mut client2 = TcpStream::connect(addr).unwrap();
+        forward(&mut client1, "tag1", &[b"event 1", b"event 2"], None);
+        forward(&mut client2, "tag2", &[b"event 3"], None);
+
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for _ in 0..3 {
+                let ev = data_rx.recv().unwrap();
+                ack_tx.send(ev.seq_no).unwrap();
+                let message = ev.entry.record["message"].as_bytes().unwrap();
+                events.push((ev.tag, message.to_vec()));
+            }
+        }
+        in_thread.join().unwrap();
+
+        events.sort();
+        assert_eq!(
+            events,
+            [
+                ("tag1".to_string(), b"event 1".to_vec()),
+                ("tag1".to_string(), b"event 2".to_vec()),
+                ("tag2".to_string(), b"event 3".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener);
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let mut bad = TcpStream::connect(addr).unwrap();
+        std::io::Write::write_all(&mut bad, b"\x92\xc0\xc0").unwrap();
+        let mut good = TcpStream::connect(addr).unwrap();
+        forward(&mut good, "tag", &[b"event"], None);
+
+        {
+            let ack_tx = ack_tx;
+            let ev = data_rx.recv().unwrap();
+            ack_tx.send(ev.seq_no).unwrap();
+            assert_eq!(ev.entry.record["message"], Value::from("event".as_bytes()));
+        }
+        in_thread.join().unwrap();
+    }
+
+    #[test]
+    fn shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let shutdown = Shutdown::new();
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener)
+            .without_heartbeat()
+            .with_shutdown(shutdown.clone());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        forward(&mut client, "tag", &[b"event 1", b"event 2"], None);
+        let ev = data_rx.recv().unwrap();
+        ack_tx.send(ev.seq_no).unwrap();
+        shutdown.trigger();
+        drop(ack_tx);
+        in_thread.join().unwrap();
+        // An event may have been sent before the shutdown.
+        assert!(data_rx.iter().count() <= 1);
+    }
+
+    #[test]
+    fn chunk_ack() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener);
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        forward(
+            &mut client,
+            "tag",
+            &[b"event 1", b"event 2"],
+            Some("chunk1"),
+        );
+        let first = data_rx.recv().unwrap();
+        let second = data_rx.recv().unwrap();
+
+        ack_tx.send(second.seq_no).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let err = client.read(&mut [0; 1]).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+
+        ack_tx.send(first.seq_no).unwrap();
+        client.set_read_timeout(None).unwrap();
+        let response: HashMap<String, String> = rmp_serde::from_read(&mut client).unwrap();
+        assert_eq!(response["ack"], "chunk1");
+
+        drop(ack_tx);
+        drop(data_rx);
+        in_thread.join().unwrap();
+    }
+
+    #[test]
+    fn duplicate_ack() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener);
+        let in_thread = thread::spawn(move || input.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        forward(
+            &mut client,
+            "tag",
+            &[b"event 1", b"event 2"],
+            Some("chunk1"),
+        );
+        let first = data_rx.recv().unwrap();
+        ack_tx.send(first.seq_no).unwrap();
+        ack_tx.send(first.seq_no).unwrap();
+
+        drop(ack_tx);
+        drop(data_rx);
+        assert!(matches!(in_thread.join().unwrap(), Err(Error::Fatal(_))));
+    }
+
+    #[test]
+    fn unread_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let shutdown = Shutdown::new();
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener)
+            .with_shutdown(shutdown.clone());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        // A client that sends chunks with large IDs but never reads the
+        // responses, which soon fill up the socket buffers.
+        let mut stuck = TcpStream::connect(addr).unwrap();
+        let stuck_thread = thread::spawn(move || {
+            let prefix = "x".repeat(64 * 1024);
+            for i in 0..400 {
+                let mut record = HashMap::new();
+                record.insert("message".to_string(), Value::from(b"event".as_ref()));
+                let msg = ForwardMode {
+                    tag: "stuck".to_string(),
+                    entries: vec![Entry {
+                        time: 1.into(),
+                        record,
+                    }],
+                    option: Some(Options {
+                        chunk: Some(format!("{prefix}{i}")),
+                        ..Options::default()
+                    }),
+                };
+                if rmp_serde::encode::write(&mut stuck, &msg).is_err() {
+                    break;
+                }
+            }
+        });
+        let (filled_tx, filled_rx) = crossbeam_channel::bounded(1);
+        let acker = thread::spawn(move || {
+            let mut stuck_events = 0;
+            for ev in data_rx {
+                if ev.tag == "stuck" {
+                    stuck_events += 1;
+                    if stuck_events == 300 {
+                        filled_tx.send(()).unwrap();
+                    }
+                }
+                if ack_tx.send(ev.seq_no).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // The input keeps taking chunks from the client even though it cannot
+        // respond to them.
+        filled_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        let mut client = TcpStream::connect(addr).unwrap();
+        forward(&mut client, "tag", &[b"event 1"], Some("chunk1"));
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let response: HashMap<String, String> = rmp_serde::from_read(&mut client).unwrap();
+        assert_eq!(response["ack"], "chunk1");
+
+        shutdown.trigger();
+        in_thread.join().unwrap();
+        acker.join().unwrap();
+        stuck_thread.join().unwrap();
+    }
+
+    #[test]
+    fn security() {
+        let security = Security {
+            self_hostname: "server".to_string(),
+            shared_key: "key".to_string(),
+            users: Vec::new(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input =
+            fluentd::Input::with_listener(data_tx, ack_rx, listener).with_security(security);
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let mut intruder = TcpStream::connect(addr).unwrap();
+        forward(&mut intruder, "tag", &[b"intrusion"], None);
+        // The server sends HELO and closes the connection.
+        let _ = intruder.read_to_end(&mut Vec::new());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let client_security = Security {
+            self_hostname: "client".to_string(),
+            shared_key: "key".to_string(),
+            users: Vec::new(),
+        };
+        fluentd::authenticate(&mut client, &client_security).unwrap();
+        forward(&mut client, "tag", &[b"event"], None);
+
+        {
+            let ack_tx = ack_tx;
+            let ev = data_rx.recv().unwrap();
+            ack_tx.send(ev.seq_no).unwrap();
+            assert_eq!(ev.entry.record["message"], Value::from("event".as_bytes()));
+        }
+        in_thread.join().unwrap();
+    }
+
+    #[test]
+    fn heartbeat() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded::<crate::SeqNo>(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener);
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let mut buf = [0xff; 2];
+        let mut response = None;
+        // The responder may not be ready yet.
+        for _ in 0..100 {
+            socket.send_to(b"\0", addr).unwrap();
+            if let Ok((len, from)) = socket.recv_from(&mut buf) {
+                response = Some((len, from));
+                break;
+            }
+        }
+        assert_eq!(response, Some((1, addr)));
+        assert_eq!(buf[0], 0);
+
+        drop(ack_tx);
+        drop(data_rx);
+        in_thread.join().unwrap();
+    }
+}
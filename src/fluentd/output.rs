@@ -0,0 +1,324 @@
+//! Sending events to Fluentd servers over TCP.
+
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::{auth, ForwardMode, Options, Security};
+
+const DEFAULT_RETRY_LIMIT: usize = 5;
+const DEFAULT_RETRY_WAIT: Duration = Duration::from_secs(1);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A response to a message with the `chunk` option.
+#[derive(Deserialize)]
+struct AckResponse {
+    ack: String,
+}
+
+/// Event writer for Fluentd, acting as a Forward Protocol client.
+///
+/// It keeps sending messages to one of its peers until it fails, and then
+/// fails over to the next peer.
+pub struct Output<T> {
+    data_channel: crossbeam_channel::Receiver<T>,
+    peers: Vec<String>,
+    security: Option<Security>,
+    ack_timeout: Option<Duration>,
+    timeout: Duration,
+    retry_limit: usize,
+    retry_wait: Duration,
+    conn: Option<TcpStream>,
+    current: usize,
+    chunk_prefix: String,
+    next_chunk: u64,
+}
+
+impl<T> Output<T>
+where
+    T: Into<ForwardMode>,
+{
+    /// Creates an event writer for Fluentd servers at `peers`, each of which is
+    /// given as `host:port`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `peers` is empty, or it fails to generate a random
+    /// chunk ID prefix.
+    pub fn new(
+        data_channel: crossbeam_channel::Receiver<T>,
+        peers: Vec<String>,
+    ) -> io::Result<Self> {
+        if peers.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no peers"));
+        }
+        Ok(Self {
+            data_channel,
+            peers,
+            security: None,
+            ack_timeout: None,
+            timeout: DEFAULT_TIMEOUT,
+            retry_limit: DEFAULT_RETRY_LIMIT,
+            retry_wait: DEFAULT_RETRY_WAIT,
+            conn: None,
+            current: 0,
+            chunk_prefix: auth::hex(&auth::random_salt()?),
+            next_chunk: 0,
+        })
+    }
+
+    /// Authenticates itself to peers in the handshake phase.
+    #[must_use]
+    pub fn with_security(mut self, security: Security) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    /// Asks peers to acknowledge each message, and waits at most `timeout` for
+    /// the acknowledgement before sending the message again.
+    #[must_use]
+    pub fn with_ack(mut self, timeout: Duration) -> Self {
+        self.ack_timeout = Some(timeout);
+        self
+    }
+
+    /// Gives up on a peer that does not accept a connection, complete the
+    /// handshake, or take a message within `timeout`, and fails over to the
+    /// next peer. The default is ten seconds. A zero `timeout` is treated as
+    /// one millisecond.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Tries all peers again at most `limit` times, waiting for `wait` before
+    /// each retry, when none of them accepts a message. The default is five
+    /// retries with one second of wait.
+    #[must_use]
+    pub fn with_retry(mut self, limit: usize, wait: Duration) -> Self {
+        self.retry_limit = limit;
+        self.retry_wait = wait;
+        self
+    }
+
+    /// Sends messages received through `data_channel` to a peer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if message serialization fails, or no peer accepts a
+    /// message after retries.
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut buf = Vec::new();
+        while let Ok(msg) = self.data_channel.recv() {
+            let mut msg: ForwardMode = msg.into();
+            let chunk = if self.ack_timeout.is_some() {
+                let chunk = format!("{}{:016x}", self.chunk_prefix, self.next_chunk);
+                self.next_chunk = self.next_chunk.wrapping_add(1);
+                msg.option.get_or_insert_with(Options::default).chunk = Some(chunk.clone());
+                Some(chunk)
+            } else {
+                None
+            };
+            rmp_serde::encode::write(&mut buf, &msg)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.send(&buf, chunk.as_deref())?;
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, msg: &[u8], chunk: Option<&str>) -> io::Result<()> {
+        let mut last_error = None;
+        for retry in 0..=self.retry_limit {
+            if retry > 0 {
+                thread::sleep(self.retry_wait);
+            }
+            for _ in 0..self.peers.len() {
+                match self.try_send(msg, chunk) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        self.conn = None;
+                        self.current = (self.current + 1) % self.peers.len();
+                        last_error = Some(e);
+                    }
+                }
+            }
+        }
+        Err(last_error.expect("tried at least once"))
+    }
+
+    fn try_send(&mut self, msg: &[u8], chunk: Option<&str>) -> io::Result<()> {
+        if self.conn.is_none() {
+            let mut conn = connect(&self.peers[self.current], self.timeout)?;
+            conn.set_write_timeout(Some(self.timeout))?;
+            conn.set_read_timeout(Some(self.timeout))?;
+            if let Some(security) = &self.security {
+                auth::authenticate(&mut conn, security)?;
+            }
+            self.conn = Some(conn);
+        }
+        let conn = self.conn.as_mut().expect("connected");
+        conn.write_all(msg)?;
+
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+        conn.set_read_timeout(self.ack_timeout)?;
+        let response: AckResponse = rmp_serde::from_read(&mut *conn).map_err(|e| match e {
+            rmp_serde::decode::Error::InvalidMarkerRead(e)
+            | rmp_serde::decode::Error::InvalidDataRead(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        })?;
+        if response.ack != chunk {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected ack for {chunk}, got {}", response.ack),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Connects to the first address `peer` resolves to that accepts a connection
+/// within `timeout`.
+fn connect(peer: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in peer.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(conn) => return Ok(conn),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot resolve {peer}"),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::fluentd::{self, Entry, ForwardMode, Security, Value};
+    use crate::Input;
+
+    fn message(text: &[u8]) -> ForwardMode {
+        let mut record = HashMap::new();
+        record.insert("message".to_string(), Value::from(text));
+        ForwardMode {
+            tag: "tag".to_string(),
+            entries: vec![Entry {
+                time: 1.into(),
+                record,
+            }],
+            option: None,
+        }
+    }
+
+    fn unused_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn forward_output() {
+        let security = Security {
+            self_hostname: "host".to_string(),
+            shared_key: "key".to_string(),
+            users: Vec::new(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener)
+            .with_security(security.clone());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let (out_tx, out_rx) = crossbeam_channel::bounded(1);
+        let mut output = fluentd::Output::new(out_rx, vec![unused_addr(), addr])
+            .unwrap()
+            .with_security(security)
+            .with_ack(Duration::from_secs(10))
+            .with_retry(0, Duration::ZERO);
+        let out_thread = thread::spawn(move || output.run());
+        out_tx.send(message(b"event 1")).unwrap();
+        out_tx.send(message(b"event 2")).unwrap();
+        drop(out_tx);
+
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for _ in 0..2 {
+                let ev = data_rx.recv().unwrap();
+                ack_tx.send(ev.seq_no).unwrap();
+                events.push(ev.entry.record["message"].as_bytes().unwrap().to_vec());
+            }
+            assert!(out_thread.join().unwrap().is_ok());
+        }
+        in_thread.join().unwrap();
+
+        assert_eq!(events, [b"event 1", b"event 2"]);
+    }
+
+    #[test]
+    fn unresponsive_peer() {
+        let security = Security {
+            self_hostname: "host".to_string(),
+            shared_key: "key".to_string(),
+            users: Vec::new(),
+        };
+        // Connections to this listener are never accepted, so the handshake
+        // never starts.
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap().to_string();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = fluentd::Input::with_listener(data_tx, ack_rx, listener)
+            .with_security(security.clone());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let (out_tx, out_rx) = crossbeam_channel::bounded(1);
+        let mut output = fluentd::Output::new(out_rx, vec![silent_addr, addr])
+            .unwrap()
+            .with_security(security)
+            .with_timeout(Duration::from_millis(100))
+            .with_retry(0, Duration::ZERO);
+        let out_thread = thread::spawn(move || output.run());
+        out_tx.send(message(b"event")).unwrap();
+        drop(out_tx);
+
+        {
+            let ack_tx = ack_tx;
+            let ev = data_rx.recv().unwrap();
+            ack_tx.send(ev.seq_no).unwrap();
+            assert_eq!(ev.entry.record["message"].as_bytes(), Some(&b"event"[..]));
+            assert!(out_thread.join().unwrap().is_ok());
+        }
+        drop(data_rx);
+        in_thread.join().unwrap();
+        drop(silent);
+    }
+
+    #[test]
+    fn no_peer_available() {
+        let (out_tx, out_rx) = crossbeam_channel::bounded(1);
+        let mut output = fluentd::Output::new(out_rx, vec![unused_addr(), unused_addr()])
+            .unwrap()
+            .with_retry(1, Duration::ZERO);
+        out_tx.send(message(b"event")).unwrap();
+        drop(out_tx);
+        assert!(output.run().is_err());
+    }
+}
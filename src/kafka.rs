@@ -1,56 +1,102 @@
 //! Reading/writing events from/to Apache Kafka servers.
 
-use std::convert::TryInto;
+use std::collections::VecDeque;
 use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
 use kafka::producer::{Producer, Record, RequiredAcks};
 use rmp_serde::Serializer;
 use serde::Serialize;
+#[cfg(feature = "async")]
+use tokio::sync::mpsc;
 
-use crate::fluentd::{Entry, ForwardMode};
-use crate::Error;
+#[cfg(feature = "async")]
+use crate::asynchronous::{self, Ready};
+use crate::fluentd::{Entry, ForwardMode, Value};
+use crate::AckTracker;
+use crate::{Error, Shutdown, Source};
 
 /// An event included in a Kafka message at `loc`.
 #[derive(Debug)]
 pub struct Event {
     pub entry: Entry,
     pub loc: EntryLocation,
+    raw: Raw,
+}
+
+/// Where `Event::raw` finds its bytes.
+#[derive(Debug)]
+enum Raw {
+    Field(Arc<[String]>),
+    Record(Vec<u8>),
 }
 
 impl crate::Event for Event {
     type Ack = EntryLocation;
 
     fn raw(&self) -> &[u8] {
-        self.entry
-            .record
-            .get("message")
-            .map_or(b"", |v| v.as_slice())
-    }
-
-    fn time(&self) -> super::SeqNo {
-        super::SeqNo::from_ne_bytes(self.entry.time.to_ne_bytes())
+        match &self.raw {
+            Raw::Field(path) => lookup(&self.entry, path).unwrap_or_default(),
+            Raw::Record(raw) => raw,
+        }
     }
 
     fn ack(&self) -> Self::Ack {
         self.loc
     }
+
+    fn timestamp(&self) -> Option<SystemTime> {
+        Some(self.entry.time.into())
+    }
 }
 
 /// The location of an event on a Kafka topic.
 #[derive(Copy, Clone, Debug)]
 pub struct EntryLocation {
-    remainder: u32, // # of entries in the message after this entry
     partition: i32,
     offset: i64,
 }
 
+/// The part of a record used as the raw bytes of an event.
+#[derive(Clone, Debug)]
+pub enum RecordField {
+    /// The string or binary value found by following a path of keys. The
+    /// first key is looked up in the record, and each of the others in the
+    /// map found by its previous key.
+    Path(Vec<String>),
+    /// The whole record serialized in `MessagePack`.
+    Record,
+}
+
+impl Default for RecordField {
+    fn default() -> Self {
+        Self::Path(vec!["message".to_string()])
+    }
+}
+
+/// What to do with a record that has no string or binary value at the
+/// selected path.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MissingField {
+    /// Uses an empty byte sequence as the raw bytes.
+    #[default]
+    Empty,
+    /// Drops the event, treating it as acknowledged.
+    Skip,
+    /// Stops reading with `Error::InvalidMessage`.
+    Error,
+    /// Uses the whole record serialized in `MessagePack` as the raw bytes.
+    Record,
+}
+
 /// Event reader for Apache Kafka.
 pub struct Input {
-    data_channel: Option<crossbeam_channel::Sender<Event>>,
+    data_channel: crossbeam_channel::Sender<Event>,
     ack_channel: crossbeam_channel::Receiver<EntryLocation>,
-    consumer: Consumer,
-    fetch_limit: usize,
+    shutdown: Option<Shutdown>,
+    messages: Messages,
 }
 
 impl Input {
@@ -69,21 +115,129 @@ impl Input {
         topic: String,
         fetch_limit: usize,
     ) -> Result<Self, kafka::Error> {
-        let consumer = Consumer::from_hosts(hosts)
-            .with_group(group)
-            .with_fallback_offset(FetchOffset::Earliest)
-            .with_fetch_max_bytes_per_partition(1_000_000)
-            .with_offset_storage(Some(GroupOffsetStorage::Kafka))
-            .with_client_id(client_id)
-            .with_topic(topic)
-            .create()?;
         Ok(Self {
-            data_channel: Some(data_channel),
+            data_channel,
             ack_channel,
-            consumer,
-            fetch_limit,
+            shutdown: None,
+            messages: Messages {
+                consumer: consumer(hosts, group, client_id, topic.clone())?,
+                topic,
+                fetch_limit,
+                selector: Selector::default(),
+                acks: AckTracker::new(),
+                fetched: VecDeque::new(),
+                entries: VecDeque::new(),
+                exhausted: false,
+            },
         })
     }
+
+    /// Selects the part of each record returned by `Event::raw`. The default
+    /// is the value of `message`.
+    #[must_use]
+    pub fn with_field(mut self, field: RecordField) -> Self {
+        self.messages.selector.set_field(field);
+        self
+    }
+
+    /// Sets what to do with a record lacking the selected field. The default
+    /// is `MissingField::Empty`.
+    #[must_use]
+    pub fn on_missing_field(mut self, policy: MissingField) -> Self {
+        self.messages.selector.missing_field = policy;
+        self
+    }
+
+    /// Stops reading events once `shutdown` is triggered, as described in
+    /// [`Shutdown`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+}
+
+fn consumer(
+    hosts: Vec<String>,
+    group: String,
+    client_id: String,
+    topic: String,
+) -> Result<Consumer, kafka::Error> {
+    Consumer::from_hosts(hosts)
+        .with_group(group)
+        .with_fallback_offset(FetchOffset::Earliest)
+        .with_fetch_max_bytes_per_partition(1_000_000)
+        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+        .with_client_id(client_id)
+        .with_topic(topic)
+        .create()
+}
+
+/// Chooses the raw bytes of events as configured by `RecordField` and
+/// `MissingField`.
+#[derive(Debug)]
+struct Selector {
+    field: Option<Arc<[String]>>,
+    missing_field: MissingField,
+}
+
+impl Default for Selector {
+    fn default() -> Self {
+        let mut selector = Self {
+            field: None,
+            missing_field: MissingField::default(),
+        };
+        selector.set_field(RecordField::default());
+        selector
+    }
+}
+
+impl Selector {
+    fn set_field(&mut self, field: RecordField) {
+        self.field = match field {
+            RecordField::Path(path) => Some(path.into()),
+            RecordField::Record => None,
+        };
+    }
+
+    /// Determines the raw bytes of an event for `entry`, or returns `None` if
+    /// the event should be skipped.
+    fn select(&self, entry: &Entry) -> Result<Option<Raw>, Error> {
+        let serialize = || {
+            rmp_serde::to_vec(&entry.record)
+                .map(|raw| Some(Raw::Record(raw)))
+                .map_err(|e| Error::InvalidMessage(Box::new(e)))
+        };
+        let Some(path) = &self.field else {
+            return serialize();
+        };
+        if lookup(entry, path).is_some() {
+            return Ok(Some(Raw::Field(path.clone())));
+        }
+        match self.missing_field {
+            MissingField::Empty => Ok(Some(Raw::Field(path.clone()))),
+            MissingField::Skip => Ok(None),
+            MissingField::Error => Err(Error::InvalidMessage(
+                format!("record has no field {}", path.join(".")).into(),
+            )),
+            MissingField::Record => serialize(),
+        }
+    }
+}
+
+/// Returns the string or binary value at `path` in the record of `entry`.
+fn lookup<'a>(entry: &'a Entry, path: &[String]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let mut value = entry.record.get(first)?;
+    for key in rest {
+        let Value::Map(map) = value else {
+            return None;
+        };
+        value = map
+            .iter()
+            .find_map(|(k, v)| (k.as_str() == Some(key)).then_some(v))?;
+    }
+    value.as_bytes()
 }
 
 impl super::Input for Input {
@@ -97,109 +251,315 @@ impl super::Input for Input {
     /// Returns an error if it cannot fetch messages from Kafka, receives an
     /// invalid message, or receives an invalid ACK from `ack_channel`.
     fn run(mut self) -> Result<(), Error> {
-        let Some(data_channel) = &self.data_channel else {
-            return Err(Error::ChannelClosed);
-        };
+        crate::driver::drive_until(
+            &mut self.messages,
+            self.data_channel,
+            &self.ack_channel,
+            self.shutdown.as_ref(),
+        )
+    }
+}
 
-        let mut sel = crossbeam_channel::Select::new();
-        let send_data = sel.send(data_channel);
-        let recv_ack = sel.recv(&self.ack_channel);
+/// Messages fetched from a Kafka topic, consumed entry by entry.
+struct Messages {
+    consumer: Consumer,
+    topic: String,
+    fetch_limit: usize,
+    selector: Selector,
+    acks: AckTracker<i32, i64>, // offsets of messages by partition
+    fetched: VecDeque<(i32, i64, Vec<u8>)>, // partition, offset, and value
+    entries: VecDeque<(EntryLocation, Entry)>,
+    exhausted: bool,
+}
 
-        'poll: loop {
+impl Source for Messages {
+    type Data = Event;
+    type Ack = EntryLocation;
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            if let Some((loc, entry)) = self.entries.pop_front() {
+                let Some(raw) = self.selector.select(&entry)? else {
+                    self.ack(loc)?;
+                    continue;
+                };
+                return Ok(Some(Event { entry, loc, raw }));
+            }
+            if let Some((partition, offset, value)) = self.fetched.pop_front() {
+                let Some(entries) = decode(
+                    &value,
+                    partition,
+                    offset,
+                    &mut self.fetch_limit,
+                    &mut self.acks,
+                )?
+                else {
+                    self.fetched.clear();
+                    self.exhausted = true;
+                    return Ok(None);
+                };
+                self.entries.extend(entries);
+                continue;
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
             let messagesets = self
                 .consumer
                 .poll()
                 .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+            if messagesets.is_empty() {
+                self.exhausted = true;
+                return Ok(None);
+            }
+            for msgset in messagesets.iter() {
+                let partition = msgset.partition();
+                self.fetched.extend(
+                    msgset
+                        .messages()
+                        .iter()
+                        .map(|msg| (partition, msg.offset, msg.value.to_vec())),
+                );
+            }
+        }
+    }
+
+    fn ack(&mut self, ack: EntryLocation) -> Result<(), Error> {
+        track_ack(&mut self.acks, &ack)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        consume(&mut self.consumer, &self.topic, &mut self.acks)?;
+        self.consumer
+            .commit_consumed()
+            .map_err(|e| Error::CannotCommit(Box::new(e)))
+    }
+}
+
+/// Decodes the entries of a Kafka message at `offset` in `partition`, paired
+/// with their locations, and starts tracking their ACKs in `acks`. Returns
+/// `None` if they exceed `fetch_limit`, which is reduced by the number of
+/// entries otherwise.
+fn decode(
+    value: &[u8],
+    partition: i32,
+    offset: i64,
+    fetch_limit: &mut usize,
+    acks: &mut AckTracker<i32, i64>,
+) -> Result<Option<impl Iterator<Item = (EntryLocation, Entry)>>, Error> {
+    let fwd_msg: ForwardMode =
+        rmp_serde::from_slice(value).map_err(|e| Error::InvalidMessage(Box::new(e)))?;
+    let len = fwd_msg.entries.len();
+    if len > u32::MAX as usize {
+        return Err(Error::TooManyEvents(len));
+    }
+    let Some(remaining) = fetch_limit.checked_sub(len) else {
+        return Ok(None);
+    };
+    *fetch_limit = remaining;
+    acks.track(partition, offset, len);
+    let loc = EntryLocation { partition, offset };
+    Ok(Some(
+        fwd_msg.entries.into_iter().map(move |entry| (loc, entry)),
+    ))
+}
+
+fn track_ack(acks: &mut AckTracker<i32, i64>, ack: &EntryLocation) -> Result<(), Error> {
+    if acks.ack(&ack.partition, &ack.offset) {
+        Ok(())
+    } else {
+        Err(Error::Fatal(format!("unexpected ACK for {ack:?}")))
+    }
+}
+
+/// Marks messages as consumed up to the highest offset in each partition
+/// before which all entries have been acknowledged.
+fn consume(
+    consumer: &mut Consumer,
+    topic: &str,
+    acks: &mut AckTracker<i32, i64>,
+) -> Result<(), Error> {
+    for (partition, offset) in acks.advanced() {
+        consumer
+            .consume_message(topic, partition, offset)
+            .map_err(|e| Error::Fatal(format!("messages from Kafka have different topics: {e}")))?;
+    }
+    Ok(())
+}
+
+/// Asynchronous event reader for Apache Kafka.
+///
+/// The Kafka client is blocking, so fetching and committing run in blocking
+/// tasks of the tokio runtime.
+#[cfg(feature = "async")]
+pub struct AsyncInput {
+    data_channel: Option<mpsc::Sender<Event>>,
+    ack_channel: mpsc::Receiver<EntryLocation>,
+    consumer: Option<Consumer>, // `None` only while used by a blocking task
+    fetch_limit: usize,
+    selector: Selector,
+    acks: AckTracker<i32, i64>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncInput {
+    /// Creates `AsyncInput` that fetches at most `fetch_limit` entries from
+    /// the given Kafka topic.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to connect to Kafka as a consumer.
+    pub async fn new(
+        data_channel: mpsc::Sender<Event>,
+        ack_channel: mpsc::Receiver<EntryLocation>,
+        hosts: Vec<String>,
+        group: String,
+        client_id: String,
+        topic: String,
+        fetch_limit: usize,
+    ) -> Result<Self, kafka::Error> {
+        let consumer =
+            tokio::task::spawn_blocking(move || consumer(hosts, group, client_id, topic))
+                .await
+                .map_err(|e| kafka::Error::Io(io::Error::other(e)))??;
+        Ok(Self {
+            data_channel: Some(data_channel),
+            ack_channel,
+            consumer: Some(consumer),
+            fetch_limit,
+            selector: Selector::default(),
+            acks: AckTracker::new(),
+        })
+    }
+
+    /// Selects the part of each record returned by `Event::raw`. The default
+    /// is the value of `message`.
+    #[must_use]
+    pub fn with_field(mut self, field: RecordField) -> Self {
+        self.selector.set_field(field);
+        self
+    }
+
+    /// Sets what to do with a record lacking the selected field. The default
+    /// is `MissingField::Empty`.
+    #[must_use]
+    pub fn on_missing_field(mut self, policy: MissingField) -> Self {
+        self.selector.missing_field = policy;
+        self
+    }
+
+    /// Runs `f` on the consumer in a blocking task.
+    async fn blocking<F, T>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Consumer) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut consumer = self.consumer.take().expect("not used by another task");
+        let (consumer, output) = tokio::task::spawn_blocking(move || {
+            let output = f(&mut consumer);
+            (consumer, output)
+        })
+        .await
+        .map_err(|e| Error::Fatal(format!("Kafka consumer task failed: {e}")))?;
+        self.consumer = Some(consumer);
+        Ok(output)
+    }
+
+    async fn handle_ack(&mut self, topic: &str, ack: &EntryLocation) -> Result<(), Error> {
+        track_ack(&mut self.acks, ack)?;
+        if self.ack_channel.is_empty() {
+            self.commit(topic).await?;
+        }
+        Ok(())
+    }
+
+    async fn commit(&mut self, topic: &str) -> Result<(), Error> {
+        consume(
+            self.consumer.as_mut().expect("not used by another task"),
+            topic,
+            &mut self.acks,
+        )?;
+        self.blocking(Consumer::commit_consumed)
+            .await?
+            .map_err(|e| Error::CannotCommit(Box::new(e)))
+    }
+}
+
+#[cfg(feature = "async")]
+impl super::AsyncInput for AsyncInput {
+    type Data = Event;
+    type Ack = EntryLocation;
+
+    /// Reads events from Kafka and forwards them through `data_channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it cannot fetch messages from Kafka, receives an
+    /// invalid message, or receives an invalid ACK from `ack_channel`.
+    async fn run(mut self) -> Result<(), Error> {
+        let Some(data_channel) = self.data_channel.take() else {
+            return Err(Error::ChannelClosed);
+        };
+
+        'poll: loop {
+            let messagesets = self
+                .blocking(Consumer::poll)
+                .await?
+                .map_err(|e| Error::CannotFetch(Box::new(e)))?;
             if messagesets.is_empty() {
                 break 'poll;
             }
             for msgset in messagesets.iter() {
                 let partition = msgset.partition();
                 for msg in msgset.messages() {
-                    let fwd_msg: ForwardMode = rmp_serde::from_slice(msg.value)
-                        .map_err(|e| Error::InvalidMessage(Box::new(e)))?;
-                    if fwd_msg.entries.len() > u32::MAX as usize {
-                        return Err(Error::TooManyEvents(fwd_msg.entries.len()));
-                    }
-                    let (remaining, overflow) =
-                        self.fetch_limit.overflowing_sub(fwd_msg.entries.len());
-                    if overflow {
+                    let Some(entries) = decode(
+                        msg.value,
+                        partition,
+                        msg.offset,
+                        &mut self.fetch_limit,
+                        &mut self.acks,
+                    )?
+                    else {
                         break 'poll;
-                    }
-                    self.fetch_limit = remaining;
-                    let offset = msg.offset;
-                    for (remainder, entry) in (0..fwd_msg.entries.len()).rev().zip(fwd_msg.entries)
-                    {
+                    };
+                    for (loc, entry) in entries {
+                        let Some(raw) = self.selector.select(&entry)? else {
+                            self.handle_ack(msgset.topic(), &loc).await?;
+                            continue;
+                        };
+                        let event = Event { entry, loc, raw };
                         loop {
-                            let oper = sel.select();
-                            match oper.index() {
-                                i if i == send_data => {
-                                    let event = Event {
-                                        entry,
-                                        loc: EntryLocation {
-                                            remainder: remainder
-                                                .try_into()
-                                                .expect("remainder <= u32::MAX"),
-                                            partition,
-                                            offset,
-                                        },
-                                    };
-                                    if oper.send(data_channel, event).is_err() {
-                                        // data_channel was disconnected. Exit the
-                                        // loop and commit consumed.
-                                        break 'poll;
-                                    }
+                            match asynchronous::ready(&data_channel, &mut self.ack_channel).await {
+                                Ready::Send(permit) => {
+                                    permit.send(event);
                                     break;
                                 }
-                                i if i == recv_ack => {
-                                    let Ok(ack) = oper.recv(&self.ack_channel) else {
-                                        // ack_channel was disconnected. Exit the
-                                        // loop and commit consumed.
-                                        break 'poll;
-                                    };
-                                    handle_ack(
-                                        &self.ack_channel,
-                                        &mut self.consumer,
-                                        msgset.topic(),
-                                        &ack,
-                                    )?;
-                                }
-                                _ => unreachable!(),
+                                Ready::Ack(ack) => self.handle_ack(msgset.topic(), &ack).await?,
+                                // Either channel was disconnected. Exit the
+                                // loop and commit consumed.
+                                Ready::Closed => break 'poll,
                             }
                         }
                     }
                 }
             }
         }
-        self.data_channel = None;
-        let subs = self.consumer.subscriptions();
-        let topic = subs.keys().next().expect("subscribes to one topic");
-        for ack in &self.ack_channel {
-            handle_ack(&self.ack_channel, &mut self.consumer, topic, &ack)?;
+        drop(data_channel);
+        let topic = self
+            .consumer
+            .as_ref()
+            .expect("not used by another task")
+            .subscriptions()
+            .into_keys()
+            .next()
+            .expect("subscribes to one topic");
+        while let Some(ack) = self.ack_channel.recv().await {
+            self.handle_ack(&topic, &ack).await?;
         }
-        Ok(())
+        self.commit(&topic).await
     }
 }
 
-fn handle_ack(
-    ack_channel: &crossbeam_channel::Receiver<EntryLocation>,
-    consumer: &mut Consumer,
-    topic: &str,
-    ack: &EntryLocation,
-) -> Result<(), Error> {
-    if ack.remainder == 0 {
-        consumer
-            .consume_message(topic, ack.partition, ack.offset)
-            .map_err(|e| Error::Fatal(format!("messages from Kafka have different topics: {e}")))?;
-    }
-    if ack_channel.is_empty() {
-        consumer
-            .commit_consumed()
-            .map_err(|e| Error::CannotCommit(Box::new(e)))?;
-    }
-    Ok(())
-}
-
 /// Event writer for Apache Kafka.
 pub struct Output<T> {
     data_channel: crossbeam_channel::Receiver<T>,
@@ -248,3 +608,85 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{EntryLocation, Event, MissingField, RecordField, Selector};
+    use crate::fluentd::{Entry, Value};
+    use crate::Event as _;
+
+    fn entry() -> Entry {
+        let mut record = HashMap::new();
+        record.insert("log".to_string(), Value::from("text"));
+        record.insert(
+            "payload".to_string(),
+            Value::Map(vec![(Value::from("body"), Value::from(b"body".as_slice()))]),
+        );
+        Entry {
+            time: 1.into(),
+            record,
+        }
+    }
+
+    fn path(keys: &[&str]) -> RecordField {
+        RecordField::Path(keys.iter().map(ToString::to_string).collect())
+    }
+
+    fn raw(field: RecordField, missing_field: MissingField) -> Option<Vec<u8>> {
+        let mut selector = Selector {
+            missing_field,
+            ..Selector::default()
+        };
+        selector.set_field(field);
+        let entry = entry();
+        selector.select(&entry).unwrap().map(|raw| {
+            let event = Event {
+                entry,
+                loc: EntryLocation {
+                    partition: 0,
+                    offset: 0,
+                },
+                raw,
+            };
+            event.raw().to_vec()
+        })
+    }
+
+    #[test]
+    fn field() {
+        assert_eq!(
+            raw(path(&["log"]), MissingField::Error),
+            Some(b"text".to_vec())
+        );
+        assert_eq!(
+            raw(path(&["payload", "body"]), MissingField::Error),
+            Some(b"body".to_vec())
+        );
+    }
+
+    #[test]
+    fn whole_record() {
+        let record = raw(RecordField::Record, MissingField::Error).unwrap();
+        assert_eq!(
+            rmp_serde::from_slice::<HashMap<String, Value>>(&record).unwrap(),
+            entry().record
+        );
+    }
+
+    #[test]
+    fn missing_field() {
+        let missing = || path(&["payload", "missing"]);
+        assert_eq!(raw(missing(), MissingField::Empty), Some(Vec::new()));
+        assert_eq!(raw(missing(), MissingField::Skip), None);
+        assert!(raw(path(&["payload"]), MissingField::Record).is_some());
+        assert!(Selector::default().select(&entry()).unwrap().is_some());
+
+        let selector = Selector {
+            missing_field: MissingField::Error,
+            ..Selector::default()
+        };
+        assert!(selector.select(&entry()).is_err());
+    }
+}
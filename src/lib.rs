@@ -1,9 +1,26 @@
 //! Channel-based event I/O processors.
 //!
-//! This crate allows to collect events from text files, pcap files, and Apache
-//! Kafka servers, distribute them to multiple threads, and optionally collect
-//! them to send to Kafka.
-
+//! This crate allows to collect events from text files, pcap files, Fluentd
+//! forwarders, and Apache Kafka servers, distribute them to multiple threads,
+//! and optionally collect them to send to Kafka or Fluentd.
+//!
+//! With the `async` feature, text, mbox, pcap, and Kafka inputs also come in
+//! asynchronous versions that run on a tokio runtime and communicate through
+//! tokio channels.
+//!
+//! The `gzip`, `zstd`, `bzip2`, and `xz` features let [`decompress`] detect and
+//! decompress inputs in those formats. The `gzip` feature also lets Fluentd
+//! and Kafka inputs accept gzip-compressed messages.
+
+mod ack;
+#[cfg(feature = "async")]
+mod asynchronous;
+pub mod batch;
+mod buffer;
+pub mod checkpoint;
+mod decompress;
+mod driver;
+pub mod files;
 pub mod fluentd;
 #[cfg(feature = "kafka")]
 pub mod kafka;
@@ -13,12 +30,26 @@ pub mod ndarray;
 #[cfg(feature = "pcap")]
 pub mod pcap;
 mod pipeline;
+mod shutdown;
 pub mod text;
 
 use std::error;
 use std::fmt;
-
-pub use self::pipeline::split;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+
+pub use self::ack::AckTracker;
+pub use self::decompress::decompress;
+pub use self::driver::{drive, Source};
+pub use self::pipeline::{
+    map_ordered, split, split_batches, split_by_key, split_with_recovery, AckPolicy, Pipeline,
+    Recovery,
+};
+pub use self::shutdown::Shutdown;
 
 /// A trait for a data source that produces messages of type `Data`.
 pub trait Input {
@@ -35,37 +66,110 @@ pub trait Input {
     fn run(self) -> Result<(), Error>;
 }
 
+/// An asynchronous version of [`Input`], which sends `Data` and receives `Ack`
+/// through tokio channels instead of blocking a thread.
+#[cfg(feature = "async")]
+pub trait AsyncInput {
+    type Data;
+    type Ack;
+
+    /// Fetches events and send them as `Data`. It also receives and processes
+    /// `Ack`, which acknowledges the receipt of a certain `Data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to fetch events, or receives an invalid
+    /// `Data` or `Ack`.
+    fn run(self) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
 pub type SeqNo = usize;
 
 /// A trait for a single event from any type of data source.
 pub trait Event {
     type Ack;
 
+    /// Returns the bytes of the event.
     fn raw(&self) -> &[u8];
-    fn time(&self) -> SeqNo;
+
+    /// Returns the `Ack` that acknowledges the receipt of the event, which also
+    /// identifies the event in its input.
     fn ack(&self) -> Self::Ack;
+
+    /// Returns the identifier of the source of the event, such as a file path,
+    /// if known.
+    fn source(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the byte offset of the event in its source, if known.
+    fn offset(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns the number of bytes the event takes in its source, if known.
+    fn length(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns when the event occurred, if known. It is the wall-clock time
+    /// recorded in the event or its framing, such as the time of a Fluentd
+    /// entry or a pcap packet, and not the time the event was read.
+    fn timestamp(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Where and when an event occurred, as far as its input knows.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    /// The identifier of the source, such as a file path.
+    pub source: Option<Arc<str>>,
+    /// The byte offset of the event in the source.
+    pub offset: Option<u64>,
+    /// The number of bytes the event takes in the source, including framing
+    /// such as a newline or a packet header, which may not be in `raw`.
+    pub length: Option<u64>,
+    /// When the event occurred.
+    pub timestamp: Option<SystemTime>,
 }
 
 /// A raw event as a byte sequence.
+///
+/// `raw` may share its memory with other events read from the same input,
+/// so cloning or slicing it does not copy the bytes.
 #[derive(Debug)]
 pub struct BareEvent {
-    pub raw: Vec<u8>,
+    pub raw: Bytes,
     pub seq_no: SeqNo,
+    pub meta: Metadata,
 }
 
 impl Event for BareEvent {
     type Ack = SeqNo;
 
     fn raw(&self) -> &[u8] {
-        self.raw.as_slice()
+        &self.raw
     }
 
-    fn time(&self) -> SeqNo {
+    fn ack(&self) -> Self::Ack {
         self.seq_no
     }
 
-    fn ack(&self) -> Self::Ack {
-        self.seq_no
+    fn source(&self) -> Option<&str> {
+        self.meta.source.as_deref()
+    }
+
+    fn offset(&self) -> Option<u64> {
+        self.meta.offset
+    }
+
+    fn length(&self) -> Option<u64> {
+        self.meta.length
+    }
+
+    fn timestamp(&self) -> Option<SystemTime> {
+        self.meta.timestamp
     }
 }
 
@@ -75,11 +179,11 @@ pub enum Error {
     /// The data channel was closed.
     ChannelClosed,
     /// Cannot commit consumed events to the source.
-    CannotCommit(Box<dyn error::Error>),
+    CannotCommit(Box<dyn error::Error + Send + Sync>),
     /// Cannot fetch events from the source.
-    CannotFetch(Box<dyn error::Error>),
+    CannotFetch(Box<dyn error::Error + Send + Sync>),
     /// Cannot parse a message.
-    InvalidMessage(Box<dyn error::Error>),
+    InvalidMessage(Box<dyn error::Error + Send + Sync>),
     /// An internal error that should not occur.
     Fatal(String),
     /// Too many events to handle.
@@ -1,19 +1,29 @@
 //! Reading emails as events from an mbox.
 
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, Read, Seek};
+use std::mem;
+use std::time::{Duration, SystemTime};
 
+use bytes::Bytes;
 use nom::{bytes::complete::tag, IResult};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead};
+#[cfg(feature = "async")]
+use tokio::sync::mpsc;
 
-use crate::{BareEvent, Error};
+use crate::buffer::ReadBuf;
+use crate::checkpoint::{Checkpointer, Store};
+use crate::{batch, BareEvent, Error, Metadata, Shutdown, Source};
 
 /// An email as a byte sequence.
 pub type Event = BareEvent;
 
 /// Event reader for a mbox input.
 pub struct Input<T: Read> {
-    data_channel: Option<crossbeam_channel::Sender<Event>>,
+    data_channel: crossbeam_channel::Sender<Event>,
     ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
-    buf: BufReader<T>,
+    shutdown: Option<Shutdown>,
+    emails: Emails<T>,
 }
 
 impl<T: Read> Input<T> {
@@ -27,36 +37,360 @@ impl<T: Read> Input<T> {
         ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
         read: T,
     ) -> Result<Self, Error> {
-        let mut buf = BufReader::new(read);
-        check_magic_number(&mut buf)?;
         Ok(Self {
-            data_channel: Some(data_channel),
+            data_channel,
+            ack_channel,
+            shutdown: None,
+            emails: Emails::new(read)?,
+        })
+    }
+
+    /// Resumes reading after the checkpoint saved for `key` in `store`, and
+    /// saves a new checkpoint there whenever more emails are acknowledged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to load the checkpoint, or the input is
+    /// shorter than the checkpoint.
+    pub fn with_checkpoint<S>(mut self, store: S, key: impl Into<String>) -> Result<Self, Error>
+    where
+        S: Store + Send + 'static,
+    {
+        self.emails.resume(store, key.into())?;
+        Ok(self)
+    }
+
+    /// Stops reading emails once `shutdown` is triggered, as described in
+    /// [`Shutdown`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+}
+
+impl<T: Read + Seek> Input<T> {
+    /// Creates `Input` that reads emails from `read` as [`Input::with_read`]
+    /// does, but seeks to the checkpoint given by [`Input::with_checkpoint`]
+    /// instead of reading the emails before it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `read` is not a valid mbox.
+    pub fn with_seek(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
+        read: T,
+    ) -> Result<Self, Error> {
+        let mut input = Self::with_read(data_channel, ack_channel, read)?;
+        input.emails.set_seekable();
+        Ok(input)
+    }
+}
+
+impl<T: Read + Send + 'static> Input<T> {
+    /// Creates `batch::Input` that reads emails from `read`, and sends them in
+    /// batches, configured by `config`, through `data_channel`.
+    ///
+    /// An input that needs further configuration, such as a checkpoint, can be
+    /// created with [`Input::with_read`] and turned into batches by
+    /// [`Input::batched`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `read` is not a valid mbox.
+    pub fn with_read_batched(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        read: T,
+        config: batch::Config,
+    ) -> Result<batch::Input<Event>, Error> {
+        Ok(batch::Input::new(
+            Box::new(Emails::new(read)?),
+            data_channel,
             ack_channel,
+            config,
+            None,
+        ))
+    }
+
+    /// Turns the input into one that sends emails in batches, configured by
+    /// `config`, through `data_channel`, and receives an ACK for each batch
+    /// from `ack_channel`. The channels given when the input was created are
+    /// dropped.
+    #[must_use]
+    pub fn batched(
+        self,
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(self.emails),
+            data_channel,
+            ack_channel,
+            config,
+            self.shutdown,
+        )
+    }
+}
+
+/// Emails read from a mbox input.
+pub(crate) struct Emails<T> {
+    buf: ReadBuf<T>,
+    seq_no: super::SeqNo,
+    offset: u64, // including the "From " line of the next email
+    from: Bytes,
+    checkpointer: Option<Checkpointer>,
+}
+
+impl<T: Read> Emails<T> {
+    /// Returns an error if `read` is not a valid mbox.
+    pub(crate) fn new(read: T) -> Result<Self, Error> {
+        let mut buf = ReadBuf::new(read);
+        let from = read_from_line(&mut buf)?;
+        if from.is_empty() {
+            return Err(wrong_format());
+        }
+        Ok(Self {
             buf,
+            seq_no: 0,
+            offset: from.len() as u64,
+            from,
+            checkpointer: None,
         })
     }
+
+    /// Seeks instead of reading when skipping the emails before a checkpoint.
+    pub(crate) fn set_seekable(&mut self)
+    where
+        T: Seek,
+    {
+        self.buf.set_seekable();
+    }
+
+    /// Skips the emails before the checkpoint saved for `key` in `store`, and
+    /// saves a new checkpoint there whenever more emails are acknowledged.
+    pub(crate) fn resume<S>(&mut self, store: S, key: String) -> Result<(), Error>
+    where
+        S: Store + Send + 'static,
+    {
+        let (checkpointer, checkpoint) = Checkpointer::load(store, key)?;
+        if checkpoint.offset > self.offset - self.from.len() as u64 {
+            // The checkpoint is at the "From " line of the next email.
+            let len = checkpoint.offset.checked_sub(self.offset).ok_or_else(|| {
+                Error::CannotFetch(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "checkpoint is not at the start of an email",
+                )))
+            })?;
+            self.buf.skip(len)?;
+            self.from = read_from_line(&mut self.buf)?;
+            self.offset = checkpoint.offset + self.from.len() as u64;
+            self.seq_no = checkpoint.seq_no;
+        }
+        self.checkpointer = Some(checkpointer);
+        Ok(())
+    }
 }
 
-fn check_magic_number<T: Read>(reader: &mut BufReader<T>) -> Result<(), Error> {
-    let mut buf = vec![];
-    reader
-        .read_until(b'\n', &mut buf)
-        .map_err(|e| Error::CannotFetch(Box::new(e)))?;
-    match mbox_magic(&buf) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(Error::InvalidMessage(Box::new(io::Error::new(
-            io::ErrorKind::Other,
-            "wrong format",
-        )))),
+impl<T: Read> Source for Emails<T> {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        let start = self.offset - self.from.len() as u64;
+        let Some((email, next_from)) = read_email(&mut self.buf, &mut self.offset)? else {
+            return Ok(None);
+        };
+        let from = mem::replace(&mut self.from, next_from);
+        let end = self.offset - self.from.len() as u64;
+        self.seq_no += 1;
+        if let Some(checkpointer) = &mut self.checkpointer {
+            checkpointer.read(self.seq_no, end);
+        }
+        Ok(Some(Event {
+            raw: email,
+            seq_no: self.seq_no,
+            meta: Metadata {
+                offset: Some(start),
+                length: Some(end - start),
+                timestamp: from_line_time(&from),
+                ..Metadata::default()
+            },
+        }))
+    }
+
+    fn ack(&mut self, ack: super::SeqNo) -> Result<(), Error> {
+        match &mut self.checkpointer {
+            Some(checkpointer) => checkpointer.ack(ack),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.checkpointer {
+            Some(checkpointer) => checkpointer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads a line, which should start with "From " unless it is at the end of
+/// the input.
+fn read_from_line<T: Read>(buf: &mut ReadBuf<T>) -> Result<Bytes, Error> {
+    let end = buf
+        .line_end(0)
+        .map_err(|e| Error::CannotFetch(Box::new(e)))?
+        .unwrap_or(buf.buffered().len());
+    let line = buf.split_to(end);
+    if !line.is_empty() && mbox_magic(&line).is_err() {
+        return Err(wrong_format());
+    }
+    Ok(line)
+}
+
+fn wrong_format() -> Error {
+    Error::InvalidMessage(Box::new(io::Error::other("wrong format")))
+}
+
+/// Reads an email and the "From " line of the next email, which ends the
+/// email, adding the number of bytes read to `offset`.
+fn read_email<T: Read>(
+    buf: &mut ReadBuf<T>,
+    offset: &mut u64,
+) -> Result<Option<(Bytes, Bytes)>, Error> {
+    let mut cur = 0;
+    loop {
+        let end = buf
+            .line_end(cur)
+            .map_err(|e| Error::CannotFetch(Box::new(e)))?
+            .unwrap_or(buf.buffered().len());
+        if end == cur {
+            *offset += cur as u64;
+            if cur == 0 {
+                return Ok(None);
+            }
+            return Ok(Some((buf.split_all(), Bytes::new())));
+        }
+        if mbox_magic(&buf.buffered()[cur..end]).is_ok() {
+            *offset += end as u64;
+            let email = buf.split_to(cur);
+            return Ok(Some((email, buf.split_to(end - cur))));
+        }
+        cur = end;
+    }
+}
+
+/// Parses the date in a "From " line, such as `From sender Thu Nov 29 21:33:09
+/// 2007`, as UTC.
+fn from_line_time(line: &[u8]) -> Option<SystemTime> {
+    const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let line = std::str::from_utf8(line).ok()?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    fields.windows(5).find_map(|date| {
+        if !WEEKDAYS.contains(&date[0]) {
+            return None;
+        }
+        let month = MONTHS.iter().position(|m| *m == date[1])? + 1;
+        let day: u64 = date[2].parse().ok().filter(|d| (1..=31).contains(d))?;
+        let mut time = date[3].split(':').map(str::parse::<u64>);
+        let (Some(Ok(hour)), Some(Ok(minute)), Some(Ok(second)), None) =
+            (time.next(), time.next(), time.next(), time.next())
+        else {
+            return None;
+        };
+        if hour > 23 || minute > 59 || second > 60 {
+            return None;
+        }
+        let year: u64 = date[4].parse().ok().filter(|y| *y >= 1970)?;
+
+        // Days since the epoch in the proleptic Gregorian calendar.
+        let (year, month) = if month <= 2 {
+            (year - 1, month as u64 + 9)
+        } else {
+            (year, month as u64 - 3)
+        };
+        let days = year * 365 + year / 4 - year / 100 + year / 400 + (153 * month + 2) / 5 + day
+            - 1
+            - 719_468;
+        let secs = days * 86_400 + hour * 3_600 + minute * 60 + second;
+        Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    })
+}
+
+impl<T: Read> super::Input for Input<T> {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    /// Reads emails from mbox and forwards them through `data_channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading an email from mbox fails.
+    fn run(mut self) -> Result<(), Error> {
+        crate::driver::drive_until(
+            &mut self.emails,
+            self.data_channel,
+            &self.ack_channel,
+            self.shutdown.as_ref(),
+        )
+    }
+}
+
+/// Asynchronous event reader for a mbox input.
+#[cfg(feature = "async")]
+pub struct AsyncInput<T> {
+    data_channel: Option<mpsc::Sender<Event>>,
+    ack_channel: mpsc::Receiver<super::SeqNo>,
+    buf: tokio::io::BufReader<T>,
+    from: Vec<u8>,
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncRead + Unpin> AsyncInput<T> {
+    /// Creates `AsyncInput` that reads emails from mbox.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `read` is not a valid mbox.
+    pub async fn with_read(
+        data_channel: mpsc::Sender<Event>,
+        ack_channel: mpsc::Receiver<super::SeqNo>,
+        read: T,
+    ) -> Result<Self, Error> {
+        let mut buf = tokio::io::BufReader::new(read);
+        let mut line = vec![];
+        buf.read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+        if mbox_magic(&line).is_err() {
+            return Err(wrong_format());
+        }
+        Ok(Self {
+            data_channel: Some(data_channel),
+            ack_channel,
+            buf,
+            from: line,
+        })
     }
 }
 
-fn read_email<T: Read>(reader: &mut BufReader<T>) -> Result<Option<Vec<u8>>, Error> {
+#[cfg(feature = "async")]
+async fn read_email_async<T: AsyncBufRead + Unpin>(
+    reader: &mut T,
+    next_from: &mut Vec<u8>,
+) -> Result<Option<Vec<u8>>, Error> {
     let mut buf = vec![];
     let mut cur = 0;
     loop {
         let bytes = reader
             .read_until(b'\n', &mut buf)
+            .await
             .map_err(|e| Error::CannotFetch(Box::new(e)))?;
         if bytes == 0 {
             if buf.is_empty() {
@@ -65,14 +399,15 @@ fn read_email<T: Read>(reader: &mut BufReader<T>) -> Result<Option<Vec<u8>>, Err
             return Ok(Some(buf));
         }
         if mbox_magic(&buf[cur..]).is_ok() {
-            buf.resize(cur, 0);
+            *next_from = buf.split_off(cur);
             return Ok(Some(buf));
         }
         cur += bytes;
     }
 }
 
-impl<T: Read> super::Input for Input<T> {
+#[cfg(feature = "async")]
+impl<T: AsyncRead + Send + Unpin> super::AsyncInput for AsyncInput<T> {
     type Data = Event;
     type Ack = super::SeqNo;
 
@@ -81,43 +416,38 @@ impl<T: Read> super::Input for Input<T> {
     /// # Errors
     ///
     /// Returns an error if reading an email from mbox fails.
-    fn run(mut self) -> Result<(), Error> {
+    async fn run(mut self) -> Result<(), Error> {
         let Some(data_channel) = &self.data_channel else {
             return Err(Error::ChannelClosed);
         };
 
-        let mut sel = crossbeam_channel::Select::new();
-        let send_data = sel.send(data_channel);
-        let recv_ack = sel.recv(&self.ack_channel);
         let mut seq_no = 0;
-
-        'poll: while let Some(email) = read_email(&mut self.buf)? {
+        let mut offset = 0;
+        loop {
+            let mut next_from = Vec::new();
+            let Some(email) = read_email_async(&mut self.buf, &mut next_from).await? else {
+                break;
+            };
+            let from = mem::replace(&mut self.from, next_from);
+            let length = (from.len() + email.len()) as u64;
             seq_no += 1;
-            loop {
-                let oper = sel.select();
-                match oper.index() {
-                    i if i == send_data => {
-                        let event = Event { raw: email, seq_no };
-                        if oper.send(data_channel, event).is_err() {
-                            // data_channel was disconnected. Exit the
-                            // loop and commit consumed.
-                            break 'poll;
-                        }
-                        break;
-                    }
-                    i if i == recv_ack => {
-                        if oper.recv(&self.ack_channel).is_err() {
-                            // ack_channel was disconnected. Exit the
-                            // loop and commit consumed.
-                            break 'poll;
-                        }
-                    }
-                    _ => unreachable!(),
-                }
+            let event = Event {
+                raw: email.into(),
+                seq_no,
+                meta: Metadata {
+                    offset: Some(offset),
+                    length: Some(length),
+                    timestamp: from_line_time(&from),
+                    ..Metadata::default()
+                },
+            };
+            offset += length;
+            if !crate::asynchronous::send(data_channel, &mut self.ack_channel, event).await {
+                break;
             }
         }
         self.data_channel = None;
-        for _ in &self.ack_channel {}
+        while self.ack_channel.recv().await.is_some() {}
         Ok(())
     }
 }
@@ -130,7 +460,9 @@ fn mbox_magic(input: &[u8]) -> IResult<&[u8], &[u8]> {
 mod tests {
     use std::io::Cursor;
     use std::thread;
+    use std::time::{Duration, UNIX_EPOCH};
 
+    use crate::checkpoint::MemoryStore;
     use crate::Input;
 
     fn read_emails(text: &'static [u8]) -> Result<Vec<super::Event>, super::Error> {
@@ -187,4 +519,98 @@ mod tests {
         let res = read_emails(text).unwrap();
         assert_eq!(res.len(), 2);
     }
+
+    #[test]
+    fn metadata() {
+        let text = b"From a@example.com Thu Nov 29 21:33:09 2007\n\nfirst\nFrom b\n\nsecond\n";
+        let events = read_emails(text).unwrap();
+        let meta: Vec<_> = events
+            .into_iter()
+            .map(|ev| (ev.meta.offset, ev.meta.length, ev.meta.timestamp))
+            .collect();
+        assert_eq!(
+            meta,
+            [
+                (
+                    Some(0),
+                    Some(51),
+                    Some(UNIX_EPOCH + Duration::from_secs(1_196_371_989))
+                ),
+                (Some(51), Some(15), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn checkpoint() {
+        let text = b"From a\n\nfirst\nFrom b\n\nsecond\nFrom c\n\nthird\n";
+        let store = MemoryStore::new();
+        let read = |acked: fn(usize) -> bool| {
+            let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+            let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+            let input = super::Input::with_read(data_tx, ack_rx, Cursor::new(text.as_ref()))
+                .unwrap()
+                .with_checkpoint(store.clone(), "mbox")
+                .unwrap();
+            let in_thread = thread::spawn(move || input.run().unwrap());
+            let mut events = Vec::new();
+            {
+                let ack_tx = ack_tx;
+                for ev in data_rx {
+                    if acked(ev.seq_no) {
+                        ack_tx.send(ev.seq_no).unwrap();
+                    }
+                    events.push((ev.seq_no, ev.raw.to_vec()));
+                }
+            }
+            in_thread.join().unwrap();
+            events
+        };
+
+        assert_eq!(read(|seq_no| seq_no == 1).len(), 3);
+        assert_eq!(
+            read(|_| true),
+            [(2, b"\nsecond\n".to_vec()), (3, b"\nthird\n".to_vec())]
+        );
+        assert!(read(|_| true).is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_two_emails() {
+        use tokio::sync::mpsc;
+
+        use crate::AsyncInput;
+
+        let text = b"From a\r\n\r\nbody\r\nFrom b\r\n\r\n";
+        let (data_tx, mut data_rx) = mpsc::channel(1);
+        let (ack_tx, ack_rx) = mpsc::channel(1);
+        let input = super::AsyncInput::with_read(data_tx, ack_rx, text.as_ref())
+            .await
+            .unwrap();
+        let in_task = tokio::spawn(input.run());
+
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            while let Some(ev) = data_rx.recv().await {
+                ack_tx.send(ev.seq_no).await.unwrap();
+                events.push(ev.raw.to_vec());
+            }
+        }
+        in_task.await.unwrap().unwrap();
+
+        assert_eq!(events, [b"\r\nbody\r\n".as_slice(), b"\r\n"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_corrupted() {
+        let (data_tx, _data_rx) = tokio::sync::mpsc::channel(1);
+        let (_ack_tx, ack_rx) = tokio::sync::mpsc::channel::<usize>(1);
+        let text = b"Fr something else\r\nFrom \r\n\r\n";
+        assert!(super::AsyncInput::with_read(data_tx, ack_rx, text.as_ref())
+            .await
+            .is_err());
+    }
 }
@@ -2,15 +2,17 @@
 
 use ndarray::{Array2, Axis};
 
-use crate::{BareEvent, Error};
+use crate::buffer::Arena;
+use crate::{batch, BareEvent, Error, Metadata, Shutdown, Source};
 
 /// A single line as a byte sequence.
 pub type Event = BareEvent;
 
 pub struct Input {
-    data_channel: Option<crossbeam_channel::Sender<Event>>,
+    data_channel: crossbeam_channel::Sender<Event>,
     ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
-    data: Array2<Vec<u8>>,
+    shutdown: Option<Shutdown>,
+    rows: Rows,
 }
 
 impl Input {
@@ -21,11 +23,58 @@ impl Input {
         data: Array2<Vec<u8>>,
     ) -> Self {
         Input {
-            data_channel: Some(data_channel),
+            data_channel,
             ack_channel,
-            data,
+            shutdown: None,
+            rows: Rows::new(data),
         }
     }
+
+    /// Creates `batch::Input` that reads the rows of `data`, and sends them in
+    /// batches, configured by `config`, through `data_channel`.
+    #[must_use]
+    pub fn new_batched(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        data: Array2<Vec<u8>>,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(Rows::new(data)),
+            data_channel,
+            ack_channel,
+            config,
+            None,
+        )
+    }
+
+    /// Stops reading rows once `shutdown` is triggered, as described in
+    /// [`Shutdown`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Turns the input into one that sends rows in batches, configured by
+    /// `config`, through `data_channel`, and receives an ACK for each batch
+    /// from `ack_channel`. The channels given when the input was created are
+    /// dropped.
+    #[must_use]
+    pub fn batched(
+        self,
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(self.rows),
+            data_channel,
+            ack_channel,
+            config,
+            self.shutdown,
+        )
+    }
 }
 
 impl super::Input for Input {
@@ -33,43 +82,52 @@ impl super::Input for Input {
     type Ack = super::SeqNo;
 
     fn run(mut self) -> Result<(), Error> {
-        let Some(data_channel) = &self.data_channel else {
-            return Err(Error::ChannelClosed);
-        };
-        let mut sel = crossbeam_channel::Select::new();
-        let send_data = sel.send(data_channel);
-        let recv_ack = sel.recv(&self.ack_channel);
-
-        'poll: for (idx, row) in self.data.axis_iter(Axis(0)).enumerate() {
-            let line = row.fold(Vec::new(), |mut line, col| {
-                line.extend_from_slice(col);
-                line
-            });
-            loop {
-                let oper = sel.select();
-                match oper.index() {
-                    i if i == send_data => {
-                        let event = Event {
-                            raw: line,
-                            seq_no: idx,
-                        };
-                        if oper.send(data_channel, event).is_err() {
-                            break 'poll;
-                        }
-                        break;
-                    }
-                    i if i == recv_ack => {
-                        if oper.recv(&self.ack_channel).is_err() {
-                            break 'poll;
-                        }
-                    }
-                    _ => unreachable!(),
-                }
-            }
+        crate::driver::drive_until(
+            &mut self.rows,
+            self.data_channel,
+            &self.ack_channel,
+            self.shutdown.as_ref(),
+        )
+    }
+}
+
+/// Rows of a matrix, each of which is concatenated into an event.
+struct Rows {
+    data: Array2<Vec<u8>>,
+    next: usize,
+    arena: Arena,
+}
+
+impl Rows {
+    fn new(data: Array2<Vec<u8>>) -> Self {
+        Self {
+            data,
+            next: 0,
+            arena: Arena::default(),
+        }
+    }
+}
+
+impl Source for Rows {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        if self.next == self.data.len_of(Axis(0)) {
+            return Ok(None);
         }
-        self.data_channel = None;
-        for _ in &self.ack_channel {}
-        Ok(())
+        let row = self.data.index_axis(Axis(0), self.next);
+        self.arena.reserve(row.iter().map(Vec::len).sum());
+        for col in row {
+            self.arena.extend_from_slice(col);
+        }
+        let event = Event {
+            raw: self.arena.split(),
+            seq_no: self.next,
+            meta: Metadata::default(),
+        };
+        self.next += 1;
+        Ok(Some(event))
     }
 }
 
@@ -117,7 +175,7 @@ mod tests {
         in_thread.join().unwrap();
 
         events.sort_unstable_by_key(|e| ids.get(&e.seq_no).unwrap());
-        let raw: Vec<_> = events.into_iter().map(|e| e.raw).collect();
+        let raw: Vec<_> = events.into_iter().map(|e| e.raw.to_vec()).collect();
 
         assert_eq!(
             raw,
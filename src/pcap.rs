@@ -1,27 +1,34 @@
 //! Reading packets as events from a pcap input.
 
-use std::io::{self, Read};
+use std::io::{self, Read, Seek};
+use std::time::{Duration, SystemTime};
 
+use bytes::Bytes;
+use pcap_parser::data::{get_packetdata_ethernet, PacketData};
+use pcap_parser::nom::{self, Offset};
 use pcap_parser::{
-    create_reader, data::get_packetdata_ethernet, data::PacketData, traits::PcapReaderIterator,
-    Block, PcapBlockOwned, PcapError,
+    parse_block_be, parse_block_le, parse_pcap_frame, parse_pcap_frame_be,
+    parse_pcap_frame_modified, parse_pcap_header, parse_sectionheaderblock, Block, LegacyPcapBlock,
+    PcapError,
 };
+#[cfg(feature = "async")]
+use tokio::sync::mpsc;
 
-use crate::{BareEvent, Error};
+use crate::buffer::ReadBuf;
+use crate::checkpoint::{self, Checkpointer, Store};
+use crate::{batch, BareEvent, Error, Metadata, Shutdown, Source};
 
 /// A packet as a byte sequence;
 pub type Event = BareEvent;
-const PCAP_BUFFER_SIZE: usize = 65536;
 
 /// Event reader for a pcap input.
 pub struct Input {
-    data_channel: Option<crossbeam_channel::Sender<Event>>,
+    data_channel: crossbeam_channel::Sender<Event>,
     ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
-    iter: Box<dyn PcapReaderIterator>,
+    shutdown: Option<Shutdown>,
+    packets: Packets,
 }
 
-unsafe impl Send for Input {}
-
 impl Input {
     /// # Panics
     ///
@@ -32,11 +39,104 @@ impl Input {
         read: R,
     ) -> Self {
         Self {
-            data_channel: Some(data_channel),
+            data_channel,
             ack_channel,
-            iter: create_reader(PCAP_BUFFER_SIZE, read).expect("pcap error"),
+            shutdown: None,
+            packets: Packets::new(read).expect("pcap error"),
         }
     }
+
+    /// Creates `Input` that reads packets from `read` as [`Input::with_read`]
+    /// does, but seeks to the checkpoint given by [`Input::with_checkpoint`]
+    /// instead of reading the packets before it.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if fail to create reader
+    pub fn with_seek<R: Read + Seek + Send + 'static>(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
+        read: R,
+    ) -> Self {
+        Self {
+            data_channel,
+            ack_channel,
+            shutdown: None,
+            packets: Packets::with_seek(read).expect("pcap error"),
+        }
+    }
+
+    /// Creates `batch::Input` that reads packets from `read`, and sends them in
+    /// batches, configured by `config`, through `data_channel`.
+    ///
+    /// An input that needs further configuration, such as a checkpoint, can be
+    /// created with [`Input::with_read`] and turned into batches by
+    /// [`Input::batched`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `read` does not start with a pcap or pcapng header.
+    pub fn with_read_batched<R: Read + Send + 'static>(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        read: R,
+        config: batch::Config,
+    ) -> Result<batch::Input<Event>, Error> {
+        Ok(batch::Input::new(
+            Box::new(Packets::new(read)?),
+            data_channel,
+            ack_channel,
+            config,
+            None,
+        ))
+    }
+
+    /// Resumes reading after the checkpoint saved for `key` in `store`, and
+    /// saves a new checkpoint there whenever more packets are acknowledged.
+    ///
+    /// Only the header and, for pcapng, the blocks before the first packet are
+    /// read again, so an interface described later in the input than its first
+    /// packet is unknown after resuming, and its packets have no timestamp.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to load the checkpoint, or the input is
+    /// shorter than the checkpoint.
+    pub fn with_checkpoint<S>(mut self, store: S, key: impl Into<String>) -> Result<Self, Error>
+    where
+        S: Store + Send + 'static,
+    {
+        self.packets.resume(store, key.into())?;
+        Ok(self)
+    }
+
+    /// Stops reading packets once `shutdown` is triggered, as described in
+    /// [`Shutdown`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Turns the input into one that sends packets in batches, configured by
+    /// `config`, through `data_channel`, and receives an ACK for each batch
+    /// from `ack_channel`. The channels given when the input was created are
+    /// dropped.
+    #[must_use]
+    pub fn batched(
+        self,
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(self.packets),
+            data_channel,
+            ack_channel,
+            config,
+            self.shutdown,
+        )
+    }
 }
 
 impl super::Input for Input {
@@ -44,79 +144,459 @@ impl super::Input for Input {
     type Ack = u64;
 
     fn run(mut self) -> Result<(), Error> {
-        let Some(data_channel) = &self.data_channel else {
-            return Err(Error::ChannelClosed);
+        crate::driver::drive_until(
+            &mut self.packets,
+            self.data_channel,
+            &self.ack_channel,
+            self.shutdown.as_ref(),
+        )
+    }
+}
+
+/// Packets read from a pcap input.
+pub(crate) struct Packets {
+    buf: ReadBuf<Box<dyn Reader>>,
+    format: Format,
+    id: super::SeqNo,
+    offset: u64,
+    clock: Clock,
+    checkpointer: Option<Checkpointer>,
+}
+
+impl Packets {
+    /// Returns an error if `read` does not start with a pcap or pcapng header.
+    pub(crate) fn new<R: Read + Send + 'static>(read: R) -> Result<Self, Error> {
+        Self::with_reader(Box::new(Sequential(read)))
+    }
+
+    /// Returns an error if `read` does not start with a pcap or pcapng header.
+    ///
+    /// Seeks instead of reading the bytes before a checkpoint.
+    pub(crate) fn with_seek<R: Read + Seek + Send + 'static>(read: R) -> Result<Self, Error> {
+        Self::with_reader(Box::new(Seekable(read)))
+    }
+
+    fn with_reader(read: Box<dyn Reader>) -> Result<Self, Error> {
+        let mut buf = ReadBuf::new(read);
+        buf.set_skip(|read, len| read.skip(len));
+        let mut clock = Clock::default();
+        let (format, header_len) = loop {
+            let data = buf.buffered();
+            let header = match parse_sectionheaderblock(data) {
+                Ok(_) => Ok((Format::Ng { big_endian: false }, 0)),
+                Err(nom::Err::Incomplete(_)) => Err(None),
+                Err(_) => match parse_pcap_header(data) {
+                    Ok((_, header)) => {
+                        clock.nanosecond = header.is_nanosecond_precision();
+                        let parse: ParseFrame = if header.is_modified_format() {
+                            parse_pcap_frame_modified
+                        } else if header.is_bigendian() {
+                            parse_pcap_frame_be
+                        } else {
+                            parse_pcap_frame
+                        };
+                        Ok((Format::Legacy(parse), header.size()))
+                    }
+                    Err(nom::Err::Incomplete(_)) => Err(None),
+                    Err(_) => Err(Some(PcapError::<&[u8]>::HeaderNotRecognized)),
+                },
+            };
+            let e = match header {
+                Ok(header) => break header,
+                Err(Some(e)) => format!("{e:?}"),
+                Err(None) => match buf.fill() {
+                    Ok(0) => "unexpected end of input".to_string(),
+                    Ok(_) => continue,
+                    Err(e) => e.to_string(),
+                },
+            };
+            return Err(Error::CannotFetch(Box::new(io::Error::other(format!(
+                "cannot read pcap header: {e}"
+            )))));
         };
-        let mut sel = crossbeam_channel::Select::new();
-        let send_data = sel.send(data_channel);
-        let recv_ack = sel.recv(&self.ack_channel);
-        let mut id = 0;
-
-        'poll: loop {
-            match self.iter.next() {
-                Ok((offset, block)) => {
-                    let res = match block {
-                        PcapBlockOwned::NG(Block::EnhancedPacket(ref epb)) => {
-                            get_packetdata_ethernet(epb.data, epb.caplen as usize)
-                        }
-                        PcapBlockOwned::NG(Block::SimplePacket(ref spb)) => {
-                            get_packetdata_ethernet(spb.data, (spb.block_len1 - 16) as usize)
-                        }
-                        PcapBlockOwned::NG(_) | PcapBlockOwned::LegacyHeader(_) => None,
-                        PcapBlockOwned::Legacy(lpb) => {
-                            get_packetdata_ethernet(lpb.data, lpb.caplen as usize)
-                        }
-                    };
+        let _ = buf.split_to(header_len);
+        Ok(Self {
+            buf,
+            format,
+            id: 0,
+            offset: header_len as u64,
+            clock,
+            checkpointer: None,
+        })
+    }
 
-                    if let Some(PacketData::L2(eslice)) = res {
-                        id += 1;
-                        loop {
-                            let oper = sel.select();
-                            match oper.index() {
-                                i if i == send_data => {
-                                    let event = Event {
-                                        raw: eslice.to_vec(),
-                                        seq_no: id,
-                                    };
-                                    if oper.send(data_channel, event).is_err() {
-                                        // data_channel was disconnected. Exit the
-                                        // loop and commit consumed.
-                                        break 'poll;
-                                    }
-                                    break;
-                                }
-                                i if i == recv_ack => {
-                                    if oper.recv(&self.ack_channel).is_err() {
-                                        // ack_channel was disconnected. Exit the
-                                        // loop and commit consumed.
-                                        break 'poll;
-                                    }
-                                }
-                                _ => unreachable!(),
-                            }
+    /// Skips the packets before the checkpoint saved for `key` in `store`, and
+    /// saves a new checkpoint there whenever more packets are acknowledged.
+    ///
+    /// The blocks of a pcapng input before its first packet are read to learn
+    /// its interfaces.
+    pub(crate) fn resume<S>(&mut self, store: S, key: String) -> Result<(), Error>
+    where
+        S: Store + Send + 'static,
+    {
+        let (checkpointer, checkpoint) = Checkpointer::load(store, key)?;
+        if checkpoint.seq_no > 0 {
+            self.read_interfaces()?;
+            let len = checkpoint.offset.checked_sub(self.offset).ok_or_else(|| {
+                Error::CannotFetch(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "checkpoint is not after a packet",
+                )))
+            })?;
+            self.buf.skip(len)?;
+            self.id = checkpoint.seq_no;
+            self.offset = checkpoint.offset;
+        }
+        self.checkpointer = Some(checkpointer);
+        Ok(())
+    }
+
+    /// Reads the pcapng blocks before the next packet.
+    fn read_interfaces(&mut self) -> Result<(), Error> {
+        let Format::Ng { big_endian } = &mut self.format else {
+            return Ok(());
+        };
+        loop {
+            let data = self.buf.buffered();
+            let parse = if *big_endian {
+                parse_block_be
+            } else {
+                parse_block_le
+            };
+            let len = match parse(data) {
+                Ok((_, Block::EnhancedPacket(_) | Block::SimplePacket(_))) => {
+                    return Ok(());
+                }
+                Ok((rest, block)) => {
+                    ng_frame(&block, &mut self.clock);
+                    if let Block::SectionHeader(shb) = block {
+                        *big_endian = shb.big_endian();
+                    }
+                    data.offset(rest)
+                }
+                Err(nom::Err::Incomplete(_)) => {
+                    let read = self
+                        .buf
+                        .fill()
+                        .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+                    if read == 0 {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                    return Err(Error::CannotFetch(Box::new(io::Error::other(format!(
+                        "cannot read block from pcapng: {e:?}"
+                    )))));
+                }
+            };
+            let _ = self.buf.split_to(len);
+            self.offset += len as u64;
+        }
+    }
+
+    /// Reads the next Ethernet frame, skipping blocks without one. Returns
+    /// `None` at the end of the input.
+    ///
+    /// The frame is sliced out of the read buffer without copying.
+    fn read_packet(&mut self) -> Result<Option<Packet>, Error> {
+        loop {
+            let data = self.buf.buffered();
+            if data.is_empty() && self.fill()? == 0 {
+                return Ok(None);
+            }
+            let data = self.buf.buffered();
+            let parsed = match &mut self.format {
+                Format::Legacy(parse) => parse(data)
+                    .map(|(rest, lpb)| (data.offset(rest), legacy_frame(&lpb, &self.clock))),
+                Format::Ng { big_endian } => {
+                    let parse = if *big_endian {
+                        parse_block_be
+                    } else {
+                        parse_block_le
+                    };
+                    parse(data).map(|(rest, block)| {
+                        let frame = ng_frame(&block, &mut self.clock);
+                        if let Block::SectionHeader(shb) = block {
+                            *big_endian = shb.big_endian();
                         }
+                        (data.offset(rest), frame)
+                    })
+                }
+            };
+            let (len, frame) = match parsed {
+                Ok((len, frame)) => (
+                    len,
+                    frame.map(|(eslice, timestamp)| (data.offset(eslice), eslice.len(), timestamp)),
+                ),
+                Err(nom::Err::Incomplete(_)) => {
+                    if self.fill()? == 0 {
+                        return Err(Error::CannotFetch(Box::new(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "pcap input ends in the middle of a block",
+                        ))));
                     }
-                    self.iter.consume_noshift(offset);
+                    continue;
                 }
-                Err(PcapError::Eof) => break 'poll,
-                Err(PcapError::Incomplete(_)) => {
-                    self.iter.refill().map_err(|e| {
-                        Error::CannotFetch(Box::new(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("cannot read packet from pcap: {e:?}"),
-                        )))
-                    })?;
+                Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                    return Err(Error::CannotFetch(Box::new(io::Error::other(format!(
+                        "cannot read packet from pcap: {e:?}"
+                    )))));
                 }
-                Err(e) => {
-                    return Err(Error::CannotFetch(Box::new(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("cannot read packet from pcap: {e:?}"),
-                    ))));
+            };
+            let block = self.buf.split_to(len);
+            let offset = self.offset;
+            self.offset += len as u64;
+            if let Some((start, frame_len, timestamp)) = frame {
+                return Ok(Some(Packet {
+                    data: block.slice(start..start + frame_len),
+                    offset,
+                    length: len as u64,
+                    timestamp,
+                }));
+            }
+        }
+    }
+
+    fn fill(&mut self) -> Result<usize, Error> {
+        self.buf.fill().map_err(|e| Error::CannotFetch(Box::new(e)))
+    }
+}
+
+impl Source for Packets {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        let Some(packet) = self.read_packet()? else {
+            return Ok(None);
+        };
+        self.id += 1;
+        if let Some(checkpointer) = &mut self.checkpointer {
+            checkpointer.read(self.id, self.offset);
+        }
+        Ok(Some(Event {
+            raw: packet.data,
+            seq_no: self.id,
+            meta: Metadata {
+                offset: Some(packet.offset),
+                length: Some(packet.length),
+                timestamp: packet.timestamp,
+                ..Metadata::default()
+            },
+        }))
+    }
+
+    fn ack(&mut self, ack: super::SeqNo) -> Result<(), Error> {
+        match &mut self.checkpointer {
+            Some(checkpointer) => checkpointer.ack(ack),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.checkpointer {
+            Some(checkpointer) => checkpointer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An input of `Packets`.
+trait Reader: Read + Send {
+    /// Moves forward by `len` bytes without reading them.
+    fn skip(&mut self, len: u64) -> Result<(), Error>;
+}
+
+/// A reader that skips bytes by reading them.
+struct Sequential<R>(R);
+
+impl<R: Read> Read for Sequential<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read + Send> Reader for Sequential<R> {
+    fn skip(&mut self, len: u64) -> Result<(), Error> {
+        checkpoint::skip(&mut self.0, len)
+    }
+}
+
+/// A reader that skips bytes by seeking.
+struct Seekable<R>(R);
+
+impl<R: Read> Read for Seekable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read + Seek + Send> Reader for Seekable<R> {
+    fn skip(&mut self, len: u64) -> Result<(), Error> {
+        checkpoint::seek(&mut self.0, len)
+    }
+}
+
+/// An Ethernet frame with its block in the input.
+struct Packet {
+    data: Bytes,
+    offset: u64,
+    length: u64,
+    timestamp: Option<SystemTime>,
+}
+
+/// A function parsing a record in a pcap file.
+type ParseFrame = fn(&[u8]) -> nom::IResult<&[u8], LegacyPcapBlock<'_>, PcapError<&[u8]>>;
+
+/// The format of a pcap input.
+enum Format {
+    Legacy(ParseFrame),
+    /// pcapng, whose byte order may change at each section header.
+    Ng {
+        big_endian: bool,
+    },
+}
+
+/// The timestamp formats of the current pcap file or pcapng section.
+#[derive(Default)]
+struct Clock {
+    nanosecond: bool,
+    /// The resolution in units per second and the offset in seconds of each
+    /// pcapng interface.
+    interfaces: Vec<(Option<u64>, i64)>,
+}
+
+/// Returns the Ethernet frame in a pcap record, and when it was captured.
+fn legacy_frame<'a>(
+    lpb: &LegacyPcapBlock<'a>,
+    clock: &Clock,
+) -> Option<(&'a [u8], Option<SystemTime>)> {
+    let Some(PacketData::L2(eslice)) = get_packetdata_ethernet(lpb.data, lpb.caplen as usize)
+    else {
+        return None;
+    };
+    let nanos = if clock.nanosecond {
+        u64::from(lpb.ts_usec)
+    } else {
+        u64::from(lpb.ts_usec) * 1_000
+    };
+    let timestamp = SystemTime::UNIX_EPOCH
+        .checked_add(Duration::from_secs(lpb.ts_sec.into()) + Duration::from_nanos(nanos));
+    Some((eslice, timestamp))
+}
+
+/// Returns the Ethernet frame in a pcapng block, if any, and when it was
+/// captured. Keeps track of the timestamp formats of the interfaces in
+/// `clock`.
+fn ng_frame<'a>(block: &Block<'a>, clock: &mut Clock) -> Option<(&'a [u8], Option<SystemTime>)> {
+    let (res, timestamp) = match block {
+        Block::EnhancedPacket(epb) => {
+            let ts = (u64::from(epb.ts_high) << 32) | u64::from(epb.ts_low);
+            let timestamp = clock
+                .interfaces
+                .get(epb.if_id as usize)
+                .and_then(|&(resolution, offset)| pcapng_time(ts, resolution?, offset));
+            (
+                get_packetdata_ethernet(epb.data, epb.caplen as usize),
+                timestamp,
+            )
+        }
+        Block::SimplePacket(spb) => (
+            get_packetdata_ethernet(spb.data, (spb.block_len1 - 16) as usize),
+            None,
+        ),
+        Block::SectionHeader(_) => {
+            clock.interfaces.clear();
+            return None;
+        }
+        Block::InterfaceDescription(idb) => {
+            clock
+                .interfaces
+                .push((idb.ts_resolution(), idb.ts_offset()));
+            return None;
+        }
+        _ => return None,
+    };
+    match res {
+        Some(PacketData::L2(eslice)) => Some((eslice, timestamp)),
+        _ => None,
+    }
+}
+
+/// Converts a pcapng timestamp in `resolution` units per second, plus `offset`
+/// seconds, to a `SystemTime`.
+fn pcapng_time(ts: u64, resolution: u64, offset: i64) -> Option<SystemTime> {
+    let nanos = u128::from(ts % resolution) * 1_000_000_000 / u128::from(resolution);
+    let time = SystemTime::UNIX_EPOCH
+        .checked_add(Duration::new(ts / resolution, u32::try_from(nanos).ok()?))?;
+    let offset_secs = Duration::from_secs(offset.unsigned_abs());
+    if offset >= 0 {
+        time.checked_add(offset_secs)
+    } else {
+        time.checked_sub(offset_secs)
+    }
+}
+
+/// Asynchronous event reader for a pcap input.
+///
+/// Parsing pcap is blocking, so it runs in a blocking task of the tokio
+/// runtime.
+#[cfg(feature = "async")]
+pub struct AsyncInput<R> {
+    data_channel: Option<mpsc::Sender<Event>>,
+    ack_channel: mpsc::Receiver<super::SeqNo>,
+    read: R,
+}
+
+#[cfg(feature = "async")]
+impl<R: Read + Send + 'static> AsyncInput<R> {
+    pub fn with_read(
+        data_channel: mpsc::Sender<Event>,
+        ack_channel: mpsc::Receiver<super::SeqNo>,
+        read: R,
+    ) -> Self {
+        Self {
+            data_channel: Some(data_channel),
+            ack_channel,
+            read,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: Read + Send + 'static> super::AsyncInput for AsyncInput<R> {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    async fn run(mut self) -> Result<(), Error> {
+        let Some(data_channel) = &self.data_channel else {
+            return Err(Error::ChannelClosed);
+        };
+
+        let (packet_tx, mut packet_rx) = mpsc::channel(1);
+        let read = self.read;
+        let reader = tokio::task::spawn_blocking(move || {
+            let mut packets = Packets::new(read)?;
+            while let Some(event) = packets.next_event()? {
+                if packet_tx.blocking_send(event).is_err() {
+                    break;
                 }
             }
+            Ok(())
+        });
+
+        while let Some(event) = packet_rx.recv().await {
+            if !crate::asynchronous::send(data_channel, &mut self.ack_channel, event).await {
+                break;
+            }
         }
+        drop(packet_rx);
+        reader
+            .await
+            .map_err(|e| Error::Fatal(format!("pcap reader failed: {e}")))??;
         self.data_channel = None;
-        for _ in &self.ack_channel {}
+        while self.ack_channel.recv().await.is_some() {}
         Ok(())
     }
 }
@@ -125,25 +605,31 @@ impl super::Input for Input {
 mod tests {
     use std::io::Cursor;
     use std::thread;
+    use std::time::{Duration, UNIX_EPOCH};
 
-    use pcap_parser::{LegacyPcapBlock, PcapHeader, ToVec};
+    use pcap_parser::{
+        EnhancedPacketBlock, InterfaceDescriptionBlock, LegacyPcapBlock, Linktype, PcapHeader,
+        SectionHeaderBlock, ToVec,
+    };
 
+    use crate::checkpoint::MemoryStore;
     use crate::{pcap, Input};
 
+    /// Creates a pcap with 10 packets of 27 bytes, captured a second apart.
     fn create_pcap() -> Cursor<Vec<u8>> {
         let fake_content = b"fake packet";
-        let pkt = LegacyPcapBlock {
-            ts_sec: 0,
-            ts_usec: 0,
-            caplen: fake_content.len() as u32,
-            origlen: fake_content.len() as u32,
-            data: fake_content,
-        }
-        .to_vec_raw()
-        .unwrap();
         let mut buf = PcapHeader::new().to_vec_raw().unwrap();
-        for _ in 0..10 {
-            buf.extend(pkt.iter());
+        for i in 0..10 {
+            let pkt = LegacyPcapBlock {
+                ts_sec: 1_600_000_000 + i,
+                ts_usec: 250,
+                caplen: fake_content.len().try_into().unwrap(),
+                origlen: fake_content.len().try_into().unwrap(),
+                data: fake_content,
+            }
+            .to_vec_raw()
+            .unwrap();
+            buf.extend(pkt);
         }
         Cursor::new(buf)
     }
@@ -155,19 +641,214 @@ mod tests {
         let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
         let in_thread = thread::spawn(move || {
             let input = pcap::Input::with_read(data_tx, ack_rx, tester);
-            input.run().unwrap()
+            input.run().unwrap();
         });
 
         let mut events = Vec::new();
         {
             let ack_tx = ack_tx;
             for ev in data_rx {
-                events.push(ev.raw);
                 ack_tx.send(ev.seq_no).unwrap();
+                events.push(ev);
+            }
+        }
+        in_thread.join().unwrap();
+
+        assert_eq!(events.len(), 10);
+        let last = events.last().unwrap();
+        assert_eq!(last.meta.offset, Some(24 + 27 * 9));
+        assert_eq!(last.meta.length, Some(27));
+        assert_eq!(
+            last.meta.timestamp,
+            Some(UNIX_EPOCH + Duration::new(1_600_000_009, 250_000))
+        );
+    }
+
+    /// Creates a pcapng with 3 packets captured a second apart. Returns it
+    /// with the length of the blocks before the packets and of each packet
+    /// block.
+    fn create_pcapng() -> (Vec<u8>, u64, u64) {
+        let mut buf = SectionHeaderBlock {
+            block_type: 0,
+            block_len1: 0,
+            bom: 0,
+            major_version: 0,
+            minor_version: 0,
+            section_len: -1,
+            options: Vec::new(),
+            block_len2: 0,
+        }
+        .to_vec()
+        .unwrap();
+        buf.extend(
+            InterfaceDescriptionBlock {
+                block_type: 0,
+                block_len1: 0,
+                linktype: Linktype::ETHERNET,
+                reserved: 0,
+                snaplen: 0,
+                options: Vec::new(),
+                block_len2: 0,
+                if_tsresol: 6,
+                if_tsoffset: 0,
+            }
+            .to_vec()
+            .unwrap(),
+        );
+        let header_len = buf.len() as u64;
+        let mut block_len = 0;
+        for i in 0..3 {
+            let ts: u64 = (1_600_000_000 + i) * 1_000_000 + 250;
+            let block = EnhancedPacketBlock {
+                block_type: 0,
+                block_len1: 0,
+                if_id: 0,
+                ts_high: u32::try_from(ts >> 32).unwrap(),
+                ts_low: u32::try_from(ts & 0xffff_ffff).unwrap(),
+                caplen: 11,
+                origlen: 11,
+                data: b"fake packet",
+                options: Vec::new(),
+                block_len2: 0,
+            }
+            .to_vec()
+            .unwrap();
+            block_len = block.len() as u64;
+            buf.extend(block);
+        }
+
+        (buf, header_len, block_len)
+    }
+
+    #[test]
+    fn pcapng_input() {
+        let (buf, header_len, block_len) = create_pcapng();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = pcap::Input::with_read(data_tx, ack_rx, Cursor::new(buf));
+        let in_thread = thread::spawn(move || input.run().unwrap());
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for ev in data_rx {
+                ack_tx.send(ev.seq_no).unwrap();
+                events.push(ev);
             }
         }
         in_thread.join().unwrap();
 
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|ev| ev.raw == b"fake packet".as_ref()));
+        let last = events.last().unwrap();
+        assert_eq!(last.meta.offset, Some(header_len + block_len * 2));
+        assert_eq!(last.meta.length, Some(block_len));
+        assert_eq!(
+            last.meta.timestamp,
+            Some(UNIX_EPOCH + Duration::new(1_600_000_002, 250_000))
+        );
+    }
+
+    #[test]
+    fn checkpoint() {
+        let store = MemoryStore::new();
+        let read = |acked: fn(usize) -> bool| {
+            let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+            let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+            let input = pcap::Input::with_read(data_tx, ack_rx, create_pcap())
+                .with_checkpoint(store.clone(), "pcap")
+                .unwrap();
+            let in_thread = thread::spawn(move || input.run().unwrap());
+            let mut seq_nos = Vec::new();
+            {
+                let ack_tx = ack_tx;
+                for ev in data_rx {
+                    if acked(ev.seq_no) {
+                        ack_tx.send(ev.seq_no).unwrap();
+                    }
+                    seq_nos.push(ev.seq_no);
+                }
+            }
+            in_thread.join().unwrap();
+            seq_nos
+        };
+
+        assert_eq!(read(|seq_no| seq_no != 4).len(), 10);
+        assert_eq!(read(|_| true), (4..=10).collect::<Vec<_>>());
+        assert!(read(|_| true).is_empty());
+    }
+
+    #[test]
+    fn seek_checkpoint() {
+        let read = |store: &MemoryStore, buf: Vec<u8>, acked: usize| {
+            let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+            let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+            let input = pcap::Input::with_seek(data_tx, ack_rx, Cursor::new(buf))
+                .with_checkpoint(store.clone(), "pcap")
+                .unwrap();
+            let in_thread = thread::spawn(move || input.run().unwrap());
+            let mut events = Vec::new();
+            {
+                let ack_tx = ack_tx;
+                for ev in data_rx {
+                    if ev.seq_no <= acked {
+                        ack_tx.send(ev.seq_no).unwrap();
+                    }
+                    events.push(ev);
+                }
+            }
+            in_thread.join().unwrap();
+            events
+        };
+
+        // The packets before the checkpoint are not parsed again.
+        let store = MemoryStore::new();
+        let mut buf = create_pcap().into_inner();
+        assert_eq!(read(&store, buf.clone(), 4).len(), 10);
+        buf[24..24 + 27 * 4].fill(0xff);
+        let events = read(&store, buf, 10);
+        assert_eq!(events.first().unwrap().seq_no, 5);
+        assert_eq!(events.first().unwrap().meta.offset, Some(24 + 27 * 4));
+        assert_eq!(events.len(), 6);
+
+        // The interfaces of pcapng are still known after resuming.
+        let store = MemoryStore::new();
+        let (mut buf, header_len, block_len) = create_pcapng();
+        assert_eq!(read(&store, buf.clone(), 1).len(), 3);
+        let packets = usize::try_from(header_len).unwrap();
+        buf[packets..packets + usize::try_from(block_len).unwrap()].fill(0xff);
+        let events = read(&store, buf, 3);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].seq_no, 2);
+        assert_eq!(events[0].meta.offset, Some(header_len + block_len));
+        assert_eq!(
+            events[0].meta.timestamp,
+            Some(UNIX_EPOCH + Duration::new(1_600_000_001, 250_000))
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_pcap_input() {
+        use tokio::sync::mpsc;
+
+        use crate::AsyncInput;
+
+        let (data_tx, mut data_rx) = mpsc::channel(1);
+        let (ack_tx, ack_rx) = mpsc::channel(1);
+        let input = pcap::AsyncInput::with_read(data_tx, ack_rx, create_pcap());
+        let in_task = tokio::spawn(input.run());
+
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            while let Some(ev) = data_rx.recv().await {
+                ack_tx.send(ev.seq_no).await.unwrap();
+                events.push(ev.raw.to_vec());
+            }
+        }
+        in_task.await.unwrap().unwrap();
+
         assert_eq!(events.len(), 10);
+        assert!(events.iter().all(|raw| raw == b"fake packet"));
     }
 }
@@ -1,8 +1,21 @@
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::ops::ControlFlow;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::thread::{self, JoinHandle};
 
-use crate::Event;
+use crate::batch::{self, Batch};
+use crate::{Error, Event, Input};
 
 /// Spawns worker threads to process events in parallel.
+///
+/// Each worker returns the result of `finalize` once `data_rx` is
+/// disconnected and drained, e.g., after the input is shut down by a
+/// [`Shutdown`](crate::Shutdown) token.
 pub fn split<D, A, I, O, F, S, R>(
     data_rx: crossbeam_channel::Receiver<D>,
     ack_tx: crossbeam_channel::Sender<A>,
@@ -19,6 +32,500 @@ where
     O: 'static + Fn(S, &D) -> S + Clone + Send,
     F: 'static + Fn(S) -> R + Clone + Send,
     R: 'static + Send,
+{
+    spawn(
+        data_rx,
+        ack_tx,
+        initialize,
+        move |s, ev: &D| (ControlFlow::Continue(fold(s, ev)), true),
+        |ev: &D| ev.ack().into(),
+        finalize,
+        nthreads,
+    )
+}
+
+/// Spawns worker threads to process batches of events, such as those sent by
+/// [`batch::Input`], in parallel.
+///
+/// Each event in a batch is passed to `fold` as in [`split`], and each batch
+/// is acknowledged once all its events are processed.
+pub fn split_batches<D, A, I, O, F, S, R>(
+    data_rx: crossbeam_channel::Receiver<Batch<D>>,
+    ack_tx: crossbeam_channel::Sender<A>,
+    initialize: I,
+    fold: O,
+    finalize: F,
+    nthreads: usize,
+) -> Vec<JoinHandle<R>>
+where
+    D: 'static + Send,
+    batch::Ack: Into<A>,
+    A: 'static + Send,
+    I: 'static + Fn() -> S + Clone + Send,
+    O: 'static + Fn(S, &D) -> S + Clone + Send,
+    F: 'static + Fn(S) -> R + Clone + Send,
+    R: 'static + Send,
+{
+    spawn(
+        data_rx,
+        ack_tx,
+        initialize,
+        move |s, batch: &Batch<D>| {
+            let s = batch.events().iter().fold(s, &fold);
+            (ControlFlow::Continue(s), true)
+        },
+        |batch: &Batch<D>| batch.ack().into(),
+        finalize,
+        nthreads,
+    )
+}
+
+/// The number of events each worker of [`split_by_key`] can have queued, so
+/// that a worker busy with one key does not hold up the others.
+const PARTITION_CAPACITY: usize = 64;
+
+/// Spawns worker threads to process events in parallel as [`split`] does, but
+/// sends each event to the worker chosen by the hash of its key, which `key`
+/// extracts from the event. Events with the same key are thus processed by the
+/// same worker in the order they were received, and its folded state can be
+/// kept per key without locks.
+///
+/// Each worker returns the result of `finalize`, or, if `key` panicked, the
+/// [`Error::Fatal`] describing the panic once it processes the events sent
+/// before it. No more events are taken from `data_rx` after such a panic.
+pub fn split_by_key<D, A, K, Q, I, O, F, S, R>(
+    data_rx: crossbeam_channel::Receiver<D>,
+    ack_tx: crossbeam_channel::Sender<A>,
+    key: K,
+    initialize: I,
+    fold: O,
+    finalize: F,
+    nthreads: usize,
+) -> Vec<JoinHandle<Result<R, Error>>>
+where
+    D: 'static + Send + Event,
+    <D as Event>::Ack: Into<A>,
+    A: 'static + Send,
+    K: 'static + Fn(&D) -> Q + Send,
+    Q: Hash,
+    I: 'static + Fn() -> S + Clone + Send,
+    O: 'static + Fn(S, &D) -> S + Clone + Send,
+    F: 'static + Fn(S) -> R + Clone + Send,
+    R: 'static + Send,
+{
+    let mut workers = Vec::new();
+    let mut partitions = Vec::new();
+    let panicked: Arc<OnceLock<String>> = Arc::default();
+    for ack_tx in vec![ack_tx; nthreads] {
+        let (tx, rx) = crossbeam_channel::bounded(PARTITION_CAPACITY);
+        partitions.push(tx);
+        let fold = fold.clone();
+        let finalize = finalize.clone();
+        let panicked = panicked.clone();
+        workers.extend(spawn(
+            rx,
+            ack_tx,
+            initialize.clone(),
+            move |s, ev: &D| (ControlFlow::Continue(fold(s, ev)), true),
+            |ev: &D| ev.ack().into(),
+            move |s| match panicked.get() {
+                Some(message) => Err(Error::Fatal(message.clone())),
+                None => Ok(finalize(s)),
+            },
+            1,
+        ));
+    }
+    if partitions.is_empty() {
+        return workers;
+    }
+    thread::spawn(move || {
+        for ev in data_rx {
+            let hash = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut hasher = DefaultHasher::new();
+                key(&ev).hash(&mut hasher);
+                hasher.finish()
+            }));
+            let hash = match hash {
+                Ok(hash) => hash,
+                Err(payload) => {
+                    // Set before `partitions` is dropped, which lets the
+                    // workers finish.
+                    let _ = panicked.set(panic_message(payload.as_ref()));
+                    break;
+                }
+            };
+            let i = usize::try_from(hash % partitions.len() as u64).unwrap_or_default();
+            if partitions[i].send(ev).is_err() {
+                break;
+            }
+        }
+    });
+    workers
+}
+
+/// What a worker does with the ACK of an event whose `fold` panicked.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AckPolicy {
+    /// Acknowledges the event, so that the input moves past it as if it was
+    /// processed.
+    #[default]
+    Ack,
+    /// Does not acknowledge the event, so that the checkpoint of the input
+    /// stays before it, and the event is read again after the input restarts.
+    Withhold,
+}
+
+/// How [`split_with_recovery`] handles a panic in `fold`.
+///
+/// By default, a worker that panics acknowledges the event, reports the panic
+/// as the result of its thread, and stops, while the other workers keep
+/// running.
+#[derive(Clone, Debug, Default)]
+pub struct Recovery {
+    restart: bool,
+    ack: AckPolicy,
+    errors: Option<crossbeam_channel::Sender<Error>>,
+}
+
+impl Recovery {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restarts a worker that panicked with a fresh state from `initialize`,
+    /// instead of stopping it. The state folded before the panic is lost.
+    #[must_use]
+    pub fn restart(self) -> Self {
+        Self {
+            restart: true,
+            ..self
+        }
+    }
+
+    /// Handles the ACK of the event whose `fold` panicked by `policy`.
+    #[must_use]
+    pub fn with_ack_policy(self, policy: AckPolicy) -> Self {
+        Self {
+            ack: policy,
+            ..self
+        }
+    }
+
+    /// Sends an [`Error::Fatal`] for each panic through `errors`, whether or
+    /// not the worker restarts.
+    #[must_use]
+    pub fn with_errors(self, errors: crossbeam_channel::Sender<Error>) -> Self {
+        Self {
+            errors: Some(errors),
+            ..self
+        }
+    }
+}
+
+/// Spawns worker threads to process events in parallel as [`split`] does, but
+/// catches a panic in `fold` and handles it as `recovery` specifies.
+///
+/// Each worker returns the result of `finalize`, or the [`Error::Fatal`]
+/// describing the panic that stopped it.
+pub fn split_with_recovery<D, A, I, O, F, S, R>(
+    data_rx: crossbeam_channel::Receiver<D>,
+    ack_tx: crossbeam_channel::Sender<A>,
+    initialize: I,
+    fold: O,
+    finalize: F,
+    nthreads: usize,
+    recovery: Recovery,
+) -> Vec<JoinHandle<Result<R, Error>>>
+where
+    D: 'static + Send + Event,
+    <D as Event>::Ack: Into<A>,
+    A: 'static + Send,
+    I: 'static + Fn() -> S + Clone + Send,
+    O: 'static + Fn(S, &D) -> S + Clone + Send,
+    F: 'static + Fn(S) -> R + Clone + Send,
+    R: 'static + Send,
+{
+    let initialize_again = initialize.clone();
+    spawn(
+        data_rx,
+        ack_tx,
+        initialize,
+        move |s, ev: &D| match panic::catch_unwind(AssertUnwindSafe(|| fold(s, ev))) {
+            Ok(next) => (ControlFlow::Continue(next), true),
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                if let Some(errors) = &recovery.errors {
+                    let _ = errors.send(Error::Fatal(message.clone()));
+                }
+                let step = if recovery.restart {
+                    ControlFlow::Continue(initialize_again())
+                } else {
+                    ControlFlow::Break(Err(Error::Fatal(message)))
+                };
+                (step, recovery.ack == AckPolicy::Ack)
+            }
+        },
+        |ev: &D| ev.ack().into(),
+        move |s| Ok(finalize(s)),
+        nthreads,
+    )
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("worker panicked: {message}")
+}
+
+/// Spawns worker threads to transform events in parallel, and returns a
+/// channel that receives the results in the order the events were received
+/// from `data_rx`, which can be passed to an output such as `kafka::Output`.
+///
+/// A collector thread puts the results back in order, holding those that
+/// finish early. At most `capacity` events are taken from `data_rx` ahead of
+/// the next result to be sent, which bounds the number of results held. An
+/// event is acknowledged once its result is sent.
+///
+/// The threads stop when `data_rx` is disconnected and all the results are
+/// sent, or when the returned receiver is dropped. If `map` panics, the
+/// results before the event are sent, and then the channel is closed without
+/// acknowledging the event, so that the input stops. The returned handle of
+/// the collector thread returns the [`Error::Fatal`] describing the panic.
+pub fn map_ordered<D, A, M, R>(
+    data_rx: crossbeam_channel::Receiver<D>,
+    ack_tx: crossbeam_channel::Sender<A>,
+    map: M,
+    nthreads: usize,
+    capacity: usize,
+) -> (
+    crossbeam_channel::Receiver<R>,
+    JoinHandle<Result<(), Error>>,
+)
+where
+    D: 'static + Send + Event,
+    <D as Event>::Ack: Into<A>,
+    A: 'static + Send,
+    M: 'static + Fn(&D) -> R + Clone + Send,
+    R: 'static + Send,
+{
+    let capacity = capacity.max(1);
+    // A slot is taken for each event handed to a worker, and released when
+    // its result is sent.
+    let (slot_tx, slot_rx) = crossbeam_channel::bounded(capacity);
+    let (task_tx, task_rx) = crossbeam_channel::bounded(nthreads);
+    let (result_tx, result_rx) = crossbeam_channel::bounded(nthreads);
+    let (output_tx, output_rx) = crossbeam_channel::bounded(capacity);
+
+    thread::spawn(move || {
+        for (seq_no, ev) in data_rx.iter().enumerate() {
+            if slot_tx.send(()).is_err() || task_tx.send((seq_no, ev)).is_err() {
+                break;
+            }
+        }
+    });
+    for _ in 0..nthreads.max(1) {
+        let task_rx = task_rx.clone();
+        let result_tx = result_tx.clone();
+        let map = map.clone();
+        thread::spawn(move || {
+            for (seq_no, ev) in task_rx {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| map(&ev)))
+                    .map_err(|payload| panic_message(payload.as_ref()));
+                let panicked = result.is_err();
+                if result_tx.send((seq_no, ev.ack().into(), result)).is_err() || panicked {
+                    break;
+                }
+            }
+        });
+    }
+    drop(result_tx);
+    let collector = thread::spawn(move || {
+        let mut held = BTreeMap::new();
+        let mut next = 0;
+        for (seq_no, ack, result) in result_rx {
+            held.insert(seq_no, (ack, result));
+            while let Some((ack, result)) = held.remove(&next) {
+                let result = result.map_err(Error::Fatal)?;
+                if output_tx.send(result).is_err() || ack_tx.send(ack).is_err() {
+                    return Ok(());
+                }
+                let _ = slot_rx.recv();
+                next += 1;
+            }
+        }
+        Ok(())
+    });
+    (output_rx, collector)
+}
+
+/// Receives the next item from the previous part of a [`Pipeline`], with the
+/// ACK of the event it came from.
+type Stream<A, T> = Arc<dyn Fn() -> Option<(A, T)> + Send + Sync>;
+
+/// A chain of an input, processing stages, and an output, each of which runs
+/// on its own threads.
+///
+/// `Pipeline` creates the channels between them, and acknowledges an event to
+/// the input once the result of its last stage is sent to the output. If a
+/// stage fails, the others stop taking more events, and [`Pipeline::run`]
+/// returns the error.
+pub struct Pipeline<A, T> {
+    input: Box<dyn FnOnce() -> Result<(), Error> + Send>,
+    ack_tx: crossbeam_channel::Sender<A>,
+    stream: Stream<A, T>,
+    stages: Vec<JoinHandle<Result<(), Error>>>,
+    failed: Arc<AtomicBool>,
+}
+
+impl<A, D> Pipeline<A, D>
+where
+    A: 'static + Send,
+    D: 'static + Send + Event,
+    <D as Event>::Ack: Into<A>,
+{
+    /// Creates `Pipeline` that starts with the input `input` creates from a
+    /// data channel and an ACK channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `input` fails.
+    pub fn new<I, N>(input: N) -> Result<Self, Error>
+    where
+        I: 'static + Input<Data = D, Ack = A> + Send,
+        N: FnOnce(crossbeam_channel::Sender<D>, crossbeam_channel::Receiver<A>) -> Result<I, Error>,
+    {
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = input(data_tx, ack_rx)?;
+        Ok(Self {
+            input: Box::new(move || input.run()),
+            ack_tx,
+            stream: Arc::new(move || data_rx.recv().ok().map(|ev: D| (ev.ack().into(), ev))),
+            stages: Vec::new(),
+            failed: Arc::default(),
+        })
+    }
+}
+
+impl<A, T> Pipeline<A, T>
+where
+    A: 'static + Send,
+    T: 'static + Send,
+{
+    /// Adds a stage that transforms each result of the previous stage, or each
+    /// event from the input, with `process` on `nthreads` threads. Results are
+    /// passed on in the order they are ready.
+    #[must_use]
+    pub fn stage<U, P>(mut self, nthreads: usize, process: P) -> Pipeline<A, U>
+    where
+        U: 'static + Send,
+        P: 'static + Fn(T) -> Result<U, Error> + Clone + Send,
+    {
+        let (tx, rx) = crossbeam_channel::bounded(nthreads);
+        for _ in 0..nthreads.max(1) {
+            let stream = self.stream.clone();
+            let tx = tx.clone();
+            let process = process.clone();
+            let failed = self.failed.clone();
+            self.stages.push(thread::spawn(move || {
+                while let Some((ack, item)) = stream() {
+                    if failed.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let result = process(item).map_err(|e| {
+                        failed.store(true, Ordering::Relaxed);
+                        e
+                    })?;
+                    if tx.send((ack, result)).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            }));
+        }
+        Pipeline {
+            input: self.input,
+            ack_tx: self.ack_tx,
+            stream: Arc::new(move || rx.recv().ok()),
+            stages: self.stages,
+            failed: self.failed,
+        }
+    }
+
+    /// Runs the pipeline, and waits until all its threads finish.
+    ///
+    /// `output` is called on the current thread with a channel that receives
+    /// the results of the last stage. It can be a closure that creates and
+    /// runs an output such as `kafka::Output`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error from the input, the stages, and `output`, in
+    /// that order, or [`Error::Fatal`] if a thread panicked.
+    pub fn run<O>(self, output: O) -> Result<(), Error>
+    where
+        O: FnOnce(crossbeam_channel::Receiver<T>) -> Result<(), Error>,
+    {
+        let Self {
+            input,
+            ack_tx,
+            stream,
+            stages,
+            failed,
+        } = self;
+        let (output_tx, output_rx) = crossbeam_channel::bounded(1);
+        let forwarder = thread::spawn(move || {
+            while let Some((ack, item)) = stream() {
+                if failed.load(Ordering::Relaxed)
+                    || output_tx.send(item).is_err()
+                    || ack_tx.send(ack).is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let input = thread::spawn(input);
+        let output = output(output_rx);
+
+        let panicked = |_| Error::Fatal("pipeline thread panicked".to_string());
+        let mut result = input.join().map_err(panicked).and_then(|r| r);
+        for stage in stages {
+            let r = stage.join().map_err(panicked).and_then(|r| r);
+            result = result.and(r);
+        }
+        forwarder.join().map_err(panicked)?;
+        result.and(output)
+    }
+}
+
+/// What a worker does after folding a message: whether it goes on with a new
+/// state or stops with a result, and whether it acknowledges the message.
+type Step<S, R> = (ControlFlow<R, S>, bool);
+
+/// Spawns `nthreads` workers, each of which folds what it receives from
+/// `data_rx` and sends `ack` of it to `ack_tx`, as the [`Step`] returned by
+/// `fold` specifies.
+fn spawn<M, A, I, O, K, F, S, R>(
+    data_rx: crossbeam_channel::Receiver<M>,
+    ack_tx: crossbeam_channel::Sender<A>,
+    initialize: I,
+    fold: O,
+    ack: K,
+    finalize: F,
+    nthreads: usize,
+) -> Vec<JoinHandle<R>>
+where
+    M: 'static + Send,
+    A: 'static + Send,
+    I: 'static + Fn() -> S + Clone + Send,
+    O: 'static + Fn(S, &M) -> Step<S, R> + Clone + Send,
+    K: 'static + Fn(&M) -> A + Clone + Send,
+    F: 'static + Fn(S) -> R + Clone + Send,
+    R: 'static + Send,
 {
     let mut workers = Vec::new();
     let (rx, tx) = (data_rx, ack_tx);
@@ -27,14 +534,20 @@ where
         let tx = tx.clone();
         let initialize = initialize.clone();
         let fold = fold.clone();
+        let ack = ack.clone();
         let finalize = finalize.clone();
         workers.push(thread::spawn(move || {
             let mut s = initialize();
-            while let Ok(ev) = rx.recv() {
-                s = fold(s, &ev);
-                if tx.send(ev.ack().into()).is_err() {
-                    // The ack channel should not be closed before the data channel.
-                    // If that happens, just use the events received so far.
+            while let Ok(msg) = rx.recv() {
+                let (step, acked) = fold(s, &msg);
+                // The ack channel should not be closed before the data channel.
+                // If that happens, just use the events received so far.
+                let closed = acked && tx.send(ack(&msg)).is_err();
+                match step {
+                    ControlFlow::Continue(next) => s = next,
+                    ControlFlow::Break(result) => return result,
+                }
+                if closed {
                     break;
                 }
             }
@@ -46,9 +559,15 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::io::Cursor;
     use std::thread;
+    use std::time::Duration;
 
-    use crate::{text, Input};
+    use super::{AckPolicy, Recovery};
+    use crate::batch::Config;
+    use crate::checkpoint::{MemoryStore, Store};
+    use crate::{text, Error, Input, Shutdown};
 
     #[test]
     fn split() {
@@ -68,4 +587,315 @@ mod tests {
             3
         );
     }
+
+    #[test]
+    fn split_with_recovery() {
+        let text = (1..=10)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let store = MemoryStore::new();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, Cursor::new(text.into_bytes()))
+            .with_checkpoint(store.clone(), "text")
+            .unwrap();
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let (errors_tx, errors_rx) = crossbeam_channel::unbounded();
+        let recovery = Recovery::new()
+            .restart()
+            .with_ack_policy(AckPolicy::Withhold)
+            .with_errors(errors_tx);
+        let workers = super::split_with_recovery(
+            data_rx,
+            ack_tx,
+            || 0_usize,
+            |count, ev: &text::Event| {
+                assert!(ev.seq_no % 4 != 0, "event {}", ev.seq_no);
+                count + 1
+            },
+            |x| x,
+            1,
+            recovery,
+        );
+        in_thread.join().unwrap();
+        let counts: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+
+        // Only the events after the last panic are in the final state.
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts[0].as_ref().ok(), Some(&2));
+        let errors: Vec<_> = errors_rx.iter().collect();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], Error::Fatal(message) if message.contains("event 4")));
+        let mut store = store;
+        assert_eq!(store.load("text").unwrap().unwrap().seq_no, 3);
+    }
+
+    #[test]
+    fn split_with_recovery_stop() {
+        let text = (1..=10)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let store = MemoryStore::new();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, Cursor::new(text.into_bytes()))
+            .with_checkpoint(store.clone(), "text")
+            .unwrap();
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let workers = super::split_with_recovery(
+            data_rx,
+            ack_tx,
+            || 0_usize,
+            |count, ev: &text::Event| {
+                assert!(ev.seq_no != 5, "event 5");
+                count + 1
+            },
+            |x| x,
+            2,
+            Recovery::new(),
+        );
+        in_thread.join().unwrap();
+        let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+
+        // The other worker processes the rest, and the failed event is acked.
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+        let mut store = store;
+        assert_eq!(store.load("text").unwrap().unwrap().seq_no, 10);
+    }
+
+    #[test]
+    fn split_batches() {
+        let text = b"event 1\nevent 2\nevent 3\nevent 4\nevent 5\n";
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read_batched(data_tx, ack_rx, text.as_ref(), Config::new(2));
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let workers = super::split_batches(
+            data_rx,
+            ack_tx,
+            Vec::new,
+            |mut seq_nos, ev: &text::Event| {
+                seq_nos.push(ev.seq_no);
+                seq_nos
+            },
+            |x| x,
+            2,
+        );
+        in_thread.join().unwrap();
+        let mut seq_nos: Vec<_> = workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect();
+        seq_nos.sort_unstable();
+        assert_eq!(seq_nos, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn map_ordered() {
+        let text = (0..100)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, Cursor::new(text.into_bytes()));
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let (output, collector) = super::map_ordered(
+            data_rx,
+            ack_tx,
+            |ev: &text::Event| {
+                let n: u64 = std::str::from_utf8(&ev.raw).unwrap().parse().unwrap();
+                // Later events tend to finish first.
+                thread::sleep(Duration::from_millis((10 - n % 10) * 2));
+                n * 2
+            },
+            4,
+            8,
+        );
+        let results: Vec<_> = output.iter().collect();
+        in_thread.join().unwrap();
+        assert!(collector.join().unwrap().is_ok());
+        assert_eq!(results, (0..100).map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn map_ordered_panic() {
+        let text = (0..100)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, Cursor::new(text.into_bytes()));
+        let in_thread = thread::spawn(move || input.run());
+
+        let (output, collector) = super::map_ordered(
+            data_rx,
+            ack_tx,
+            |ev: &text::Event| {
+                let n: u64 = std::str::from_utf8(&ev.raw).unwrap().parse().unwrap();
+                assert_ne!(n, 42, "cannot map");
+                n
+            },
+            4,
+            8,
+        );
+        let results: Vec<_> = output.iter().collect();
+
+        // The input stops instead of waiting for the result of event 42.
+        in_thread.join().unwrap().unwrap();
+        assert!(matches!(collector.join().unwrap(), Err(Error::Fatal(_))));
+        assert_eq!(results, (0..42).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn split_by_key() {
+        let text = b"a 1\nb 1\nc 1\na 2\nb 2\na 3\nd 1\nc 2\n";
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, text.as_ref());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let workers = super::split_by_key(
+            data_rx,
+            ack_tx,
+            |ev: &text::Event| ev.raw[0],
+            Vec::new,
+            |mut lines, ev: &text::Event| {
+                lines.push(String::from_utf8(ev.raw.to_vec()).unwrap());
+                lines
+            },
+            |x| x,
+            3,
+        );
+        in_thread.join().unwrap();
+        let mut owners = HashMap::new();
+        let mut count = 0;
+        for (i, worker) in workers.into_iter().enumerate() {
+            let mut last = HashMap::new();
+            for line in worker.join().unwrap().unwrap() {
+                let (key, n) = line.split_once(' ').unwrap();
+                assert_eq!(*owners.entry(key.to_string()).or_insert(i), i);
+                // Lines with the same key are processed in order.
+                assert!(last.insert(key.to_string(), n.to_string()) < Some(n.to_string()));
+                count += 1;
+            }
+        }
+        assert_eq!(count, 8);
+        assert_eq!(owners.len(), 4);
+    }
+
+    #[test]
+    fn split_by_key_panic() {
+        let text = b"a 1\nb 1\nc 1\nd 1\n";
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, text.as_ref());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let workers = super::split_by_key(
+            data_rx,
+            ack_tx,
+            |ev: &text::Event| {
+                assert_ne!(ev.raw[0], b'c', "no key");
+                ev.raw[0]
+            },
+            || 0_usize,
+            |n, _| n + 1,
+            |x| x,
+            2,
+        );
+        in_thread.join().unwrap();
+        let results: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        assert!(results.iter().all(|r| matches!(r, Err(Error::Fatal(_)))));
+    }
+
+    #[test]
+    fn pipeline() {
+        let text = b"1\n2\n3\n4\n5\n";
+        let store = MemoryStore::new();
+        let input_store = store.clone();
+        let pipeline = super::Pipeline::new(|data_tx, ack_rx| {
+            text::Input::with_read(data_tx, ack_rx, text.as_ref())
+                .with_checkpoint(input_store, "text")
+        })
+        .unwrap()
+        .stage(2, parse)
+        .stage(3, |n| Ok(n * n));
+        let mut results = Vec::new();
+        pipeline
+            .run(|rx| {
+                results.extend(rx);
+                Ok(())
+            })
+            .unwrap();
+
+        results.sort_unstable();
+        assert_eq!(results, [1, 4, 9, 16, 25]);
+        let mut store = store;
+        assert_eq!(store.load("text").unwrap().unwrap().seq_no, 5);
+    }
+
+    #[test]
+    fn pipeline_error() {
+        let text = b"1\n2\nx\n4\n5\n";
+        let pipeline = super::Pipeline::new(|data_tx, ack_rx| {
+            Ok(text::Input::with_read(data_tx, ack_rx, text.as_ref()))
+        })
+        .unwrap()
+        .stage(2, parse);
+        let res = pipeline.run(|rx| {
+            for _ in rx {}
+            Ok(())
+        });
+        assert!(matches!(res, Err(Error::InvalidMessage(_))));
+    }
+
+    fn parse(ev: text::Event) -> Result<u64, Error> {
+        let raw =
+            String::from_utf8(ev.raw.into()).map_err(|e| Error::InvalidMessage(Box::new(e)))?;
+        raw.parse().map_err(|e| Error::InvalidMessage(Box::new(e)))
+    }
+
+    #[test]
+    fn split_shutdown() {
+        let text = "event\n".repeat(1000);
+        let store = MemoryStore::new();
+        let shutdown = Shutdown::new();
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, Cursor::new(text.into_bytes()))
+            .with_checkpoint(store.clone(), "text")
+            .unwrap()
+            .with_shutdown(shutdown.clone());
+        let in_thread = thread::spawn(move || input.run().unwrap());
+
+        let workers = super::split(
+            data_rx,
+            ack_tx,
+            || 0_usize,
+            move |sum, ev: &text::Event| {
+                if ev.seq_no == 10 {
+                    shutdown.trigger();
+                }
+                sum + 1
+            },
+            |x| x,
+            2,
+        );
+        in_thread.join().unwrap();
+        let sum = workers
+            .into_iter()
+            .map(|w| w.join().unwrap())
+            .sum::<usize>();
+        assert!((10..1000).contains(&sum));
+        let mut store = store;
+        assert_eq!(store.load("text").unwrap().unwrap().seq_no, sum);
+    }
 }
@@ -0,0 +1,79 @@
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+
+/// A token that tells inputs to stop, e.g., when a service receives SIGTERM.
+///
+/// Its clones share the same state, so one of them can be passed to each
+/// input with `with_shutdown` and another to a signal handler. Once triggered,
+/// an input stops fetching events, closes its data channel, keeps processing
+/// ACKs until its ACK channel is disconnected, commits them, and returns. The
+/// workers receiving from the data channel, such as those spawned by
+/// [`split`](crate::split), process the events left in the channel, and then
+/// return their final states.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    trigger: Arc<Mutex<Option<Sender<()>>>>,
+    triggered: Receiver<()>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (tx, rx) = crossbeam_channel::bounded(0);
+        Self {
+            trigger: Arc::new(Mutex::new(Some(tx))),
+            triggered: rx,
+        }
+    }
+}
+
+impl Shutdown {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tells the inputs holding this token or its clones to stop.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another thread panicked while triggering the same token.
+    pub fn trigger(&self) {
+        self.trigger.lock().expect("not poisoned").take();
+    }
+
+    /// Returns `true` if the token has been triggered.
+    #[must_use]
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.try_recv() == Err(TryRecvError::Disconnected)
+    }
+}
+
+/// Returns a channel that becomes ready, being disconnected, once `shutdown`
+/// is triggered, or never if there is no `shutdown`, to be used in a
+/// `crossbeam_channel::Select`.
+pub(crate) fn stop_channel(shutdown: Option<&Shutdown>) -> Receiver<()> {
+    shutdown.map_or_else(crossbeam_channel::never, |shutdown| {
+        shutdown.triggered.clone()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{stop_channel, Shutdown};
+
+    #[test]
+    fn trigger() {
+        let shutdown = Shutdown::new();
+        let token = shutdown.clone();
+        assert!(!token.is_triggered());
+        let waiter = thread::spawn(move || stop_channel(Some(&token)).recv().is_err());
+        shutdown.trigger();
+        assert!(waiter.join().unwrap());
+        assert!(shutdown.is_triggered());
+        shutdown.trigger();
+        assert!(shutdown.is_triggered());
+    }
+}
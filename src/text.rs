@@ -1,17 +1,30 @@
 //! Reading lines as events from a text input.
 
-use std::io::{BufRead, BufReader, Read};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::{BareEvent, Error};
+use bytes::Bytes;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufReadExt, AsyncRead};
+#[cfg(feature = "async")]
+use tokio::sync::mpsc;
+
+use crate::buffer::ReadBuf;
+use crate::checkpoint::{Checkpoint, Checkpointer, Store};
+use crate::{batch, BareEvent, Error, Metadata, Shutdown, Source};
 
 /// A single line as a byte sequence.
 pub type Event = BareEvent;
 
 /// Event reader for a text input.
 pub struct Input<T: Read> {
-    data_channel: Option<crossbeam_channel::Sender<Event>>,
+    data_channel: crossbeam_channel::Sender<Event>,
     ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
-    buf: BufReader<T>,
+    shutdown: Option<Shutdown>,
+    lines: Lines<T>,
 }
 
 impl<T: Read> Input<T> {
@@ -21,11 +34,154 @@ impl<T: Read> Input<T> {
         read: T,
     ) -> Self {
         Self {
-            data_channel: Some(data_channel),
+            data_channel,
             ack_channel,
-            buf: BufReader::new(read),
+            shutdown: None,
+            lines: Lines::new(read),
         }
     }
+
+    /// Resumes reading after the checkpoint saved for `key` in `store`, and
+    /// saves a new checkpoint there whenever more lines are acknowledged.
+    ///
+    /// When following a file, the checkpoint is discarded and the file is read
+    /// from the beginning if the checkpoint was saved for a file that has
+    /// since been replaced by rotation, or is beyond the end of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to load the checkpoint, or the input is
+    /// shorter than the checkpoint.
+    pub fn with_checkpoint<S>(mut self, store: S, key: impl Into<String>) -> Result<Self, Error>
+    where
+        S: Store + Send + 'static,
+    {
+        self.lines.resume(store, key.into())?;
+        Ok(self)
+    }
+
+    /// Stops reading lines once `shutdown` is triggered, as described in
+    /// [`Shutdown`].
+    #[must_use]
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+}
+
+impl<T: Read + Seek> Input<T> {
+    /// Creates `Input` that reads lines from `read` as [`Input::with_read`]
+    /// does, but seeks to the checkpoint given by [`Input::with_checkpoint`]
+    /// instead of reading the lines before it.
+    pub fn with_seek(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
+        read: T,
+    ) -> Self {
+        let mut input = Self::with_read(data_channel, ack_channel, read);
+        input.lines.set_seekable();
+        input
+    }
+}
+
+impl<T: Read + Send + 'static> Input<T> {
+    /// Creates `batch::Input` that reads lines from `read`, and sends them in
+    /// batches, configured by `config`, through `data_channel`.
+    ///
+    /// An input that needs further configuration, such as a checkpoint, can be
+    /// created with [`Input::with_read`] and turned into batches by
+    /// [`Input::batched`].
+    #[must_use]
+    pub fn with_read_batched(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        read: T,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(Lines::new(read)),
+            data_channel,
+            ack_channel,
+            config,
+            None,
+        )
+    }
+
+    /// Turns the input into one that sends lines in batches, configured by
+    /// `config`, through `data_channel`, and receives an ACK for each batch
+    /// from `ack_channel`. The channels given when the input was created are
+    /// dropped.
+    #[must_use]
+    pub fn batched(
+        self,
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        config: batch::Config,
+    ) -> batch::Input<Event> {
+        batch::Input::new(
+            Box::new(self.lines),
+            data_channel,
+            ack_channel,
+            config,
+            self.shutdown,
+        )
+    }
+}
+
+impl Input<File> {
+    /// Creates `Input` that reads lines from the file at `path` from the
+    /// beginning, and keeps following the file after reaching its end, like
+    /// `tail -F`.
+    ///
+    /// It checks the file for new lines every `interval`. A line is sent only
+    /// after its newline is written. If the file is renamed and a new file is
+    /// created at `path`, it reads the rest of the old file and continues from
+    /// the beginning of the new one. If the file is truncated in place, it
+    /// reads the file again from the beginning. On platforms other than Unix,
+    /// only truncation is detected.
+    ///
+    /// It never runs out of lines, and stops when either channel is
+    /// disconnected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to open the file.
+    pub fn follow<P: AsRef<Path>>(
+        data_channel: crossbeam_channel::Sender<Event>,
+        ack_channel: crossbeam_channel::Receiver<super::SeqNo>,
+        path: P,
+        interval: Duration,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            data_channel,
+            ack_channel,
+            shutdown: None,
+            lines: Lines::follow(path.as_ref(), interval)?,
+        })
+    }
+
+    /// Creates `batch::Input` that follows the file at `path` as
+    /// [`Input::follow`] does, and sends its lines in batches, configured by
+    /// `config`, through `data_channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if it fails to open the file.
+    pub fn follow_batched<P: AsRef<Path>>(
+        data_channel: crossbeam_channel::Sender<batch::Batch<Event>>,
+        ack_channel: crossbeam_channel::Receiver<batch::Ack>,
+        path: P,
+        interval: Duration,
+        config: batch::Config,
+    ) -> io::Result<batch::Input<Event>> {
+        Ok(batch::Input::new(
+            Box::new(Lines::follow(path.as_ref(), interval)?),
+            data_channel,
+            ack_channel,
+            config,
+            None,
+        ))
+    }
 }
 
 impl<T: Read> super::Input for Input<T> {
@@ -33,69 +189,335 @@ impl<T: Read> super::Input for Input<T> {
     type Ack = super::SeqNo;
 
     fn run(mut self) -> Result<(), Error> {
+        crate::driver::drive_until(
+            &mut self.lines,
+            self.data_channel,
+            &self.ack_channel,
+            self.shutdown.as_ref(),
+        )
+    }
+}
+
+/// Lines read from a text input.
+pub(crate) struct Lines<T> {
+    buf: ReadBuf<T>,
+    line_no: super::SeqNo,
+    offset: u64, // excluding an incomplete line left in `buf`
+    source: Option<Arc<str>>,
+    checkpointer: Option<Checkpointer>,
+    follow: Option<Follow<T>>,
+}
+
+impl<T: Read> Lines<T> {
+    pub(crate) fn new(read: T) -> Self {
+        Self {
+            buf: ReadBuf::new(read),
+            line_no: 0,
+            offset: 0,
+            source: None,
+            checkpointer: None,
+            follow: None,
+        }
+    }
+
+    /// Seeks instead of reading when skipping the lines before a checkpoint.
+    pub(crate) fn set_seekable(&mut self)
+    where
+        T: Seek,
+    {
+        self.buf.set_seekable();
+    }
+
+    /// Skips the lines before the checkpoint saved for `key` in `store`, and
+    /// saves a new checkpoint there whenever more lines are acknowledged.
+    pub(crate) fn resume<S>(&mut self, store: S, key: String) -> Result<(), Error>
+    where
+        S: Store + Send + 'static,
+    {
+        let (mut checkpointer, mut checkpoint) = Checkpointer::load(store, key)?;
+        if let Some(follow) = &self.follow {
+            // A checkpoint in a file replaced by rotation, or beyond the end
+            // of a truncated one, does not apply to the file at the path.
+            let len = fs::metadata(&follow.path)
+                .map_err(|e| Error::CannotFetch(Box::new(e)))?
+                .len();
+            if checkpoint.file != Some(follow.id) || checkpoint.offset > len {
+                checkpoint = Checkpoint::default();
+            }
+            checkpointer.set_file(follow.id);
+        }
+        if checkpoint.offset > self.offset {
+            self.buf.skip(checkpoint.offset - self.offset)?;
+            self.offset = checkpoint.offset;
+            self.line_no = checkpoint.seq_no;
+        }
+        self.checkpointer = Some(checkpointer);
+        Ok(())
+    }
+}
+
+impl<T> Lines<T> {
+    /// Makes an event from `line`, which ends at `offset`.
+    fn line(&mut self, mut line: Bytes) -> Event {
+        let length = line.len() as u64;
+        line.truncate(trimmed_len(&line));
+        self.line_no += 1;
+        if let Some(checkpointer) = &mut self.checkpointer {
+            checkpointer.read(self.line_no, self.offset);
+        }
+        Event {
+            raw: line,
+            seq_no: self.line_no,
+            meta: Metadata {
+                source: self.source.clone(),
+                offset: Some(self.offset - length),
+                length: Some(length),
+                timestamp: None,
+            },
+        }
+    }
+}
+
+impl Lines<File> {
+    /// Reads lines from the file at `path`, following it as described in
+    /// [`Input::follow`].
+    fn follow(path: &Path, interval: Duration) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        let file = File::open(&path)?;
+        let id = file_id(&file.metadata()?);
+        let mut lines = Self::new(file);
+        lines.set_seekable();
+        lines.source = Some(path.to_string_lossy().into());
+        lines.follow = Some(Follow {
+            path,
+            id,
+            interval,
+            open: |path| File::open(path),
+            renamed: None,
+        });
+        Ok(lines)
+    }
+}
+
+/// The file followed by `Lines`.
+struct Follow<T> {
+    path: PathBuf,
+    id: (u64, u64),
+    interval: Duration,
+    open: fn(&Path) -> io::Result<T>,
+    /// The file newly created at `path` after the current one was renamed.
+    renamed: Option<T>,
+}
+
+/// A change of the followed file.
+enum Change<T> {
+    Renamed(T),
+    Truncated(T),
+}
+
+impl<T> Follow<T> {
+    /// Checks whether the file at `path` has been replaced, or truncated
+    /// below `offset`, and opens it again if so.
+    fn check(&mut self, offset: u64) -> io::Result<Option<Change<T>>> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let id = file_id(&metadata);
+        if id != self.id {
+            let file = (self.open)(&self.path)?;
+            self.id = id;
+            Ok(Some(Change::Renamed(file)))
+        } else if metadata.len() < offset {
+            Ok(Some(Change::Truncated((self.open)(&self.path)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Returns the device and inode numbers of a file, which tell whether it has
+/// been replaced.
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.dev(), metadata.ino())
+}
+
+/// Returns zeros, since files cannot be told apart this way.
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+impl<T: Read> Source for Lines<T> {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    fn next_event(&mut self) -> Result<Option<Event>, Error> {
+        loop {
+            let end = self
+                .buf
+                .line_end(0)
+                .map_err(|e| Error::CannotFetch(Box::new(e)))?;
+            if let Some(end) = end {
+                let line = self.buf.split_to(end);
+                self.offset += end as u64;
+                return Ok(Some(self.line(line)));
+            }
+
+            // Reached the end of the file, with an incomplete line, if any, in
+            // the buffer.
+            let partial = self.buf.buffered().len();
+            let Some(follow) = &mut self.follow else {
+                if partial == 0 {
+                    return Ok(None);
+                }
+                let line = self.buf.split_all();
+                self.offset += partial as u64;
+                return Ok(Some(self.line(line)));
+            };
+            let id = follow.id;
+            let file = if let Some(file) = follow.renamed.take() {
+                file
+            } else {
+                match follow
+                    .check(self.offset + partial as u64)
+                    .map_err(|e| Error::CannotFetch(Box::new(e)))?
+                {
+                    None => return Ok(None),
+                    Some(Change::Renamed(file)) => {
+                        // Lines may have been appended to the old file
+                        // before the new one was created.
+                        follow.renamed = Some(file);
+                        continue;
+                    }
+                    Some(Change::Truncated(file)) => file,
+                }
+            };
+            let event = if partial == 0 {
+                None
+            } else {
+                let line = self.buf.split_all();
+                self.offset += partial as u64;
+                Some(self.line(line))
+            };
+            self.buf.set_read(file);
+            self.offset = 0;
+            if let Some(checkpointer) = &mut self.checkpointer {
+                checkpointer.set_file(id);
+            }
+            if event.is_some() {
+                return Ok(event);
+            }
+        }
+    }
+
+    fn ack(&mut self, ack: super::SeqNo) -> Result<(), Error> {
+        match &mut self.checkpointer {
+            Some(checkpointer) => checkpointer.ack(ack),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.checkpointer {
+            Some(checkpointer) => checkpointer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn poll_interval(&self) -> Option<Duration> {
+        self.follow.as_ref().map(|follow| follow.interval)
+    }
+}
+
+/// Asynchronous event reader for a text input.
+#[cfg(feature = "async")]
+pub struct AsyncInput<T> {
+    data_channel: Option<mpsc::Sender<Event>>,
+    ack_channel: mpsc::Receiver<super::SeqNo>,
+    buf: tokio::io::BufReader<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncRead> AsyncInput<T> {
+    pub fn with_read(
+        data_channel: mpsc::Sender<Event>,
+        ack_channel: mpsc::Receiver<super::SeqNo>,
+        read: T,
+    ) -> Self {
+        Self {
+            data_channel: Some(data_channel),
+            ack_channel,
+            buf: tokio::io::BufReader::new(read),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncRead + Send + Unpin> super::AsyncInput for AsyncInput<T> {
+    type Data = Event;
+    type Ack = super::SeqNo;
+
+    async fn run(mut self) -> Result<(), Error> {
         let Some(data_channel) = &self.data_channel else {
             return Err(Error::ChannelClosed);
         };
 
-        let mut sel = crossbeam_channel::Select::new();
-        let send_data = sel.send(data_channel);
-        let recv_ack = sel.recv(&self.ack_channel);
         let mut line_no = 0;
-
-        'poll: loop {
+        let mut offset = 0;
+        loop {
             let mut line = Vec::new();
             self.buf
                 .read_until(b'\n', &mut line)
+                .await
                 .map_err(|e| Error::CannotFetch(Box::new(e)))?;
-            if let Some(&b) = line.last() {
-                if b == b'\n' {
-                    line.pop();
-                    if let Some(&b) = line.last() {
-                        if b == b'\r' {
-                            line.pop();
-                        }
-                    }
-                }
-            } else {
+            if line.is_empty() {
                 break;
             }
+            let length = line.len() as u64;
+            line.truncate(trimmed_len(&line));
             line_no += 1;
-            loop {
-                let oper = sel.select();
-                match oper.index() {
-                    i if i == send_data => {
-                        let event = Event {
-                            raw: line,
-                            seq_no: line_no,
-                        };
-                        if oper.send(data_channel, event).is_err() {
-                            // data_channel was disconnected. Exit the
-                            // loop and commit consumed.
-                            break 'poll;
-                        }
-                        break;
-                    }
-                    i if i == recv_ack => {
-                        if oper.recv(&self.ack_channel).is_err() {
-                            // ack_channel was disconnected. Exit the
-                            // loop and commit consumed.
-                            break 'poll;
-                        }
-                    }
-                    _ => unreachable!(),
-                }
+            let event = Event {
+                raw: line.into(),
+                seq_no: line_no,
+                meta: Metadata {
+                    offset: Some(offset),
+                    length: Some(length),
+                    ..Metadata::default()
+                },
+            };
+            offset += length;
+            if !crate::asynchronous::send(data_channel, &mut self.ack_channel, event).await {
+                break;
             }
         }
         self.data_channel = None;
-        for _ in &self.ack_channel {}
+        while self.ack_channel.recv().await.is_some() {}
         Ok(())
     }
 }
 
+/// Returns the length of `line` without a trailing `\n` or `\r\n`.
+fn trimmed_len(line: &[u8]) -> usize {
+    match line {
+        [.., b'\r', b'\n'] => line.len() - 2,
+        [.., b'\n'] => line.len() - 1,
+        _ => line.len(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Cursor, Write};
     use std::thread;
+    use std::time::{Duration, Instant};
 
+    use crate::checkpoint::{Checkpoint, FileStore, MemoryStore, Store};
     use crate::{text, Input};
 
     #[test]
@@ -107,16 +529,236 @@ mod tests {
         let input = text::Input::with_read(data_tx, ack_rx, text.as_ref());
         let in_thread = thread::spawn(move || input.run().unwrap());
 
+        let mut events = Vec::new();
+        let mut spans = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            for ev in data_rx {
+                spans.push((ev.meta.offset.unwrap(), ev.meta.length.unwrap()));
+                events.push(ev.raw.to_vec());
+                ack_tx.send(ev.seq_no).unwrap();
+            }
+        }
+        in_thread.join().unwrap();
+
+        assert_eq!(events, [b"event 1", b"event 2", b"event 3"]);
+        assert_eq!(spans, [(0, 8), (8, 9), (17, 7)]);
+    }
+
+    #[test]
+    fn checkpoint() {
+        let text = b"event 1\nevent 2\r\nevent 3\n";
+        let store = MemoryStore::new();
+
+        // Acknowledges all but the second line.
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_read(data_tx, ack_rx, text.as_ref())
+            .with_checkpoint(store.clone(), "text")
+            .unwrap();
+        let in_thread = thread::spawn(move || input.run().unwrap());
+        {
+            let ack_tx = ack_tx;
+            for ev in data_rx {
+                if ev.seq_no != 2 {
+                    ack_tx.send(ev.seq_no).unwrap();
+                }
+            }
+        }
+        in_thread.join().unwrap();
+
+        // Resumes from the second line, seeking to it.
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::with_seek(data_tx, ack_rx, Cursor::new(text.as_ref()))
+            .with_checkpoint(store.clone(), "text")
+            .unwrap();
+        let in_thread = thread::spawn(move || input.run().unwrap());
         let mut events = Vec::new();
         {
             let ack_tx = ack_tx;
             for ev in data_rx {
-                events.push(ev.raw);
                 ack_tx.send(ev.seq_no).unwrap();
+                events.push((ev.seq_no, ev.raw.to_vec()));
             }
         }
         in_thread.join().unwrap();
 
+        assert_eq!(events, [(2, b"event 2".to_vec()), (3, b"event 3".to_vec())]);
+        let mut store = store;
+        assert_eq!(
+            store.load("text").unwrap(),
+            Some(Checkpoint {
+                offset: text.len() as u64,
+                seq_no: 3,
+                file: None
+            })
+        );
+    }
+
+    #[test]
+    fn follow() {
+        fn append(path: &std::path::Path, text: &[u8]) {
+            let mut file = OpenOptions::new().append(true).open(path).unwrap();
+            file.write_all(text).unwrap();
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, b"line 1\nline 2\n").unwrap();
+
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::follow(data_tx, ack_rx, &path, Duration::from_millis(10)).unwrap();
+        let in_thread = thread::spawn(move || input.run().unwrap());
+        let source = path.to_string_lossy().into_owned();
+        let next = move || {
+            let ev = data_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(ev.meta.source.as_deref(), Some(source.as_str()));
+            ack_tx.send(ev.seq_no).unwrap();
+            (ev.seq_no, String::from_utf8(ev.raw.to_vec()).unwrap())
+        };
+        assert_eq!(next(), (1, "line 1".to_string()));
+        assert_eq!(next(), (2, "line 2".to_string()));
+
+        // Waits for the newline of an incomplete line.
+        append(&path, b"line 3\nline");
+        assert_eq!(next(), (3, "line 3".to_string()));
+        thread::sleep(Duration::from_millis(50));
+        append(&path, b" 4\n");
+        assert_eq!(next(), (4, "line 4".to_string()));
+
+        // Rotation by renaming; the old file is written once more before the
+        // new one is created.
+        let rotated = dir.path().join("app.log.1");
+        fs::rename(&path, &rotated).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        append(&rotated, b"line 5\n");
+        File::create(&path).unwrap();
+        append(&path, b"line 6 is longer than the next one\n");
+        assert_eq!(next(), (5, "line 5".to_string()));
+        assert_eq!(
+            next(),
+            (6, "line 6 is longer than the next one".to_string())
+        );
+
+        // Rotation by copying and truncating.
+        fs::copy(&path, &rotated).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+        append(&path, b"line 7\n");
+        assert_eq!(next(), (7, "line 7".to_string()));
+
+        drop(next);
+        in_thread.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_checkpoint() {
+        fn run(path: &std::path::Path, store: &MemoryStore, acks: usize) -> Vec<(usize, String)> {
+            let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+            let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+            let input = text::Input::follow(data_tx, ack_rx, path, Duration::from_millis(10))
+                .unwrap()
+                .with_checkpoint(store.clone(), "text")
+                .unwrap();
+            let in_thread = thread::spawn(move || input.run().unwrap());
+            let mut events = Vec::new();
+            for _ in 0..acks {
+                let ev = data_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+                ack_tx.send(ev.seq_no).unwrap();
+                events.push((ev.seq_no, String::from_utf8(ev.raw.to_vec()).unwrap()));
+            }
+            drop(ack_tx);
+            drop(data_rx);
+            in_thread.join().unwrap();
+            events
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, b"line 1\nline 2\n").unwrap();
+        let store = MemoryStore::new();
+        assert_eq!(run(&path, &store, 1), [(1, "line 1".to_string())]);
+        assert_eq!(run(&path, &store, 1), [(2, "line 2".to_string())]);
+
+        // The checkpoint in the rotated file does not apply to the new one.
+        fs::rename(&path, dir.path().join("app.log.1")).unwrap();
+        fs::write(&path, b"line A\nline B is longer\n").unwrap();
+        assert_eq!(run(&path, &store, 1), [(1, "line A".to_string())]);
+    }
+
+    #[test]
+    fn follow_idle_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.log");
+        let store_path = dir.path().join("checkpoints");
+        fs::write(&path, b"line 1\nline 2\n").unwrap();
+        let store = FileStore::open(&store_path)
+            .unwrap()
+            .with_interval(Duration::from_secs(3600));
+
+        let (data_tx, data_rx) = crossbeam_channel::bounded(1);
+        let (ack_tx, ack_rx) = crossbeam_channel::bounded(1);
+        let input = text::Input::follow(data_tx, ack_rx, &path, Duration::from_millis(10))
+            .unwrap()
+            .with_checkpoint(store, "text")
+            .unwrap();
+        let in_thread = thread::spawn(move || input.run().unwrap());
+        for _ in 0..2 {
+            let ev = data_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+            ack_tx.send(ev.seq_no).unwrap();
+        }
+
+        // The last checkpoint is written while the input waits for more lines,
+        // without waiting for the interval of the store.
+        let saved = || {
+            FileStore::open(&store_path)
+                .unwrap()
+                .load("text")
+                .unwrap()
+                .map(|c| c.seq_no)
+        };
+        let start = Instant::now();
+        while saved() != Some(2) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(ack_tx);
+        drop(data_rx);
+        in_thread.join().unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_text_input() {
+        use tokio::sync::mpsc;
+
+        use crate::AsyncInput;
+
+        let text = b"event 1\nevent 2\r\nevent 3";
+
+        let (data_tx, mut data_rx) = mpsc::channel(1);
+        let (ack_tx, ack_rx) = mpsc::channel(1);
+        let input = text::AsyncInput::with_read(data_tx, ack_rx, text.as_ref());
+        let in_task = tokio::spawn(input.run());
+
+        let mut events = Vec::new();
+        {
+            let ack_tx = ack_tx;
+            while let Some(ev) = data_rx.recv().await {
+                events.push(ev.raw.to_vec());
+                ack_tx.send(ev.seq_no).await.unwrap();
+            }
+        }
+        in_task.await.unwrap().unwrap();
+
         assert_eq!(events, [b"event 1", b"event 2", b"event 3"]);
     }
 }